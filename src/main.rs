@@ -1,32 +1,109 @@
 mod service;
+use service::build_comment_trees;
+use service::check_schema;
+use service::complete_context;
 use service::consume_file;
+use service::discover_files;
 use service::read_json_config;
+use service::resolve_subreddit_ids;
+use service::resolved_parent_ids;
+use service::set_column_selection;
+use service::verify_dumps;
+use service::ContentFilter;
 use service::DBManager;
 use service::DBRedditComment;
+use service::DBRedditCommentFull;
 use service::DBRedditSubmission;
 use service::DBRedditSubmissionSmall;
 use service::DBTable;
+use service::RecordFilter;
+use service::RejectedLines;
+use service::SubredditFilter;
+use service::Thresholds;
+use service::TotalProgress;
 
 use async_channel::{Receiver, Sender};
-use std::collections::HashMap;
+use sqlx::PgPool;
+use std::sync::Arc;
 use tokio::task;
 
 #[tokio::main]
 async fn main() {
     let config = read_json_config();
 
-    // Create a hashmap for faster subreddit lookup
-    let subreddit_list: HashMap<String, bool> = config
-        .subreddit_list
-        .iter()
-        .map(|subreddit| (subreddit.to_lowercase(), true))
-        .collect();
-
     // Configure logging
-    simple_logging::log_to_file(config.log_file, log::LevelFilter::Info)
+    simple_logging::log_to_file(&config.log_file, log::LevelFilter::Info)
         .unwrap_or_else(|err| panic!("Failed to initialize logging: {}", err));
     log::info!("Starting reader");
 
+    // Compile the filter rules once, they are shared by all the workers.
+    let record_filter = Arc::new(RecordFilter::new(
+        SubredditFilter::new(&config.subreddit_list, &config.subreddit_exclude),
+        &config.authors(),
+        ContentFilter::new(&config.content_filter),
+        config.filter_combine,
+        config.filter.as_deref(),
+    ));
+    let thresholds = Arc::new(Thresholds::new(
+        &config.thresholds,
+        config.sample_rate,
+        config.sample_by_thread,
+    ));
+
+    // Resolve the list of files to ingest.
+    let files = discover_files(&config.target_folder, &config.discovery);
+    log::info!("Found {} files in {}", files.len(), config.target_folder);
+
+    let args: Vec<String> = std::env::args().collect();
+
+    // Check the dump files against a .torrent file and exit.
+    if args.get(1).map(String::as_str) == Some("verify-dumps") {
+        let torrent_path = args
+            .get(2)
+            .unwrap_or_else(|| panic!("Usage: {} verify-dumps <file.torrent>", args[0]));
+        verify_dumps(torrent_path, &config.target_folder);
+        return;
+    }
+
+    // Print the ids of the given subreddits, or of `subreddit_list`, and exit.
+    if args.get(1).map(String::as_str) == Some("resolve-subreddits") {
+        let dump = args.get(2).unwrap_or_else(|| {
+            panic!("Usage: {} resolve-subreddits <dump.zst> [name...]", args[0])
+        });
+        let names = if args.len() > 3 {
+            args[3..].to_vec()
+        } else {
+            config.subreddit_list.clone()
+        };
+        resolve_subreddit_ids(dump, &names).await;
+        return;
+    }
+
+    // Check that the records of a dump parse, and exit.
+    if args.get(1).map(String::as_str) == Some("check-schema") {
+        let dump = args
+            .get(2)
+            .unwrap_or_else(|| panic!("Usage: {} check-schema <dump>", args[0]));
+        if !check_schema(dump).await {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Print the progress of the known files and exit.
+    if args.get(1).map(String::as_str) == Some("status") {
+        TotalProgress::new(config.log_frequency).print_status();
+        return;
+    }
+
+    // Dry run: print the ingestion plan and exit.
+    if args.iter().any(|arg| arg == "--list-files") {
+        for filename in &files {
+            println!("{}", filename);
+        }
+        return;
+    }
+
     let db_mgr = DBManager::new(
         &config.database.host,
         config.database.port,
@@ -35,10 +112,60 @@ async fn main() {
         &config.database.database,
     )
     .await;
+    // Reduce the tables to the configured columns.
+    set_column_selection(
+        &config.columns,
+        &[
+            DBRedditSubmission::table_name(),
+            DBRedditSubmissionSmall::table_name(),
+            DBRedditComment::table_name(),
+            DBRedditCommentFull::table_name(),
+        ],
+    );
+
     // Check if the tables exist, and create them if they don't
     db_mgr.check_tables::<DBRedditSubmission>().await;
     db_mgr.check_tables::<DBRedditSubmissionSmall>().await;
     db_mgr.check_tables::<DBRedditComment>().await;
+    if config.ingestion.full_comments {
+        db_mgr.check_tables::<DBRedditCommentFull>().await;
+    }
+
+    // Build the comment trees of the comments already imported and exit.
+    if args.get(1).map(String::as_str) == Some("build-trees") {
+        build_trees(&db_mgr.pool, config.ingestion.full_comments).await;
+        return;
+    }
+
+    // Import the submissions and parent comments missing from the comments
+    // already imported and exit.
+    if args.get(1).map(String::as_str) == Some("complete-context") {
+        let full_comments = config.ingestion.full_comments;
+        let policy = config.parser.error_policy;
+        if full_comments {
+            complete_context::<DBRedditCommentFull>(
+                &db_mgr.pool,
+                &files,
+                &config.context,
+                full_comments,
+                policy,
+            )
+            .await;
+        } else {
+            complete_context::<DBRedditComment>(
+                &db_mgr.pool,
+                &files,
+                &config.context,
+                full_comments,
+                policy,
+            )
+            .await;
+        }
+        if config.ingestion.build_trees {
+            build_trees(&db_mgr.pool, full_comments).await;
+        }
+        return;
+    }
 
     // Print table information
     log::info!("Table information:");
@@ -56,39 +183,38 @@ async fn main() {
         db_mgr.get_table_count::<DBRedditSubmissionSmall>().await,
     );
 
+    let pool = db_mgr.pool.clone();
+
     // Queue to signal jobs are done
     let (sender_insert_done, receiver_insert_done): (Sender<()>, Receiver<()>) =
         async_channel::bounded(config.ingestion.qsize_jobs_done as usize);
 
-    let sender_insert_done_clone = sender_insert_done.clone();
+    // Output for the lines that cannot be imported.
+    let rejected = RejectedLines::open(&config.parser.rejected_lines_file);
+
+    // The done queue closes once the producer and all the workers are finished.
+    let producer_thresholds = thresholds.clone();
     let producer = task::spawn(async move {
-        for folder in std::fs::read_dir(&config.target_folder).unwrap() {
-            // Read recursively all files in the target folder
-            let mut entries: Vec<_> = match std::fs::read_dir(folder.unwrap().path()) {
-                Ok(dir) => dir
-                    .filter_map(|entry| entry.ok())
-                    .map(|entry| entry.path().to_str().unwrap().to_string())
-                    .collect(),
-                Err(err) => {
-                    log::error!("Failed to read directory: {}", err);
-                    continue;
-                }
-            };
-            entries.sort();
-            // entries.reverse();
-
-            for filename in entries {
-                consume_file(
-                    &filename,
-                    config.log_frequency,
-                    config.parser.num_workers,
-                    config.parser.qsize_to_parse,
-                    subreddit_list.clone(),
-                    config.ingestion.push_summarized_db,
-                    db_mgr.pool.clone(),
-                    sender_insert_done.clone(),
-                )
-                .await;
+        for filename in files {
+            let result = consume_file(
+                &filename,
+                config.log_frequency,
+                &config.parser,
+                record_filter.clone(),
+                producer_thresholds.clone(),
+                config.ingestion.push_summarized_db,
+                config.ingestion.full_comments,
+                config.ingestion.verify_policy,
+                &rejected,
+                db_mgr.pool.clone(),
+                sender_insert_done.clone(),
+            )
+            .await;
+
+            if let Err(err) = result {
+                log::error!("[{}] Aborting the ingestion: {}", filename, err);
+                println!("Ingestion aborted in {}: {}", filename, err);
+                break;
             }
         }
 
@@ -97,7 +223,7 @@ async fn main() {
 
     let mut last_print_timestamp = std::time::Instant::now();
     let mut total_inserts = 0;
-    while let Ok(_) = receiver_insert_done.recv().await {
+    while receiver_insert_done.recv().await.is_ok() {
         total_inserts += 1;
 
         if total_inserts % config.log_frequency != 0 {
@@ -111,11 +237,31 @@ async fn main() {
         last_print_timestamp = now;
 
         log::info!("Insert done queue size: {}", receiver_insert_done.len());
-        if Sender::is_closed(&sender_insert_done_clone) {
-            break;
-        }
     }
 
     // Wait for the producer to finish
     producer.await.unwrap();
-}
\ No newline at end of file
+
+    log::info!("Run finished, {} items processed", total_inserts);
+    println!("{} items processed", total_inserts);
+    thresholds.report();
+
+    let resolved = resolved_parent_ids();
+    if resolved > 0 {
+        log::info!("{} integer parent ids resolved", resolved);
+        println!("{} integer parent ids resolved", resolved);
+    }
+
+    if config.ingestion.build_trees {
+        build_trees(&pool, config.ingestion.full_comments).await;
+    }
+}
+
+// Build the comment trees of the table the comments are stored in.
+async fn build_trees(pool: &PgPool, full_comments: bool) {
+    if full_comments {
+        build_comment_trees::<DBRedditCommentFull>(pool).await;
+    } else {
+        build_comment_trees::<DBRedditComment>(pool).await;
+    }
+}
@@ -0,0 +1,91 @@
+// Reddit ids are base 36 numbers, written in lowercase.
+const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Split a fullname such as `t3_abc` into its kind, `t3`, and its id, `abc`.
+/// Ids without a kind prefix are returned as they are. The prefix is matched
+/// like `^t[0-9]_` in the `reader_base36` SQL function.
+pub fn split_kind(id: &str) -> (Option<&str>, &str) {
+    match id.as_bytes() {
+        [b't', digit, b'_', ..] if digit.is_ascii_digit() => (Some(&id[..2]), &id[3..]),
+        _ => (None, id),
+    }
+}
+
+/// Decode an id, with or without its kind prefix. Returns None for ids that
+/// are not base 36 or do not fit in an i64.
+pub fn decode(id: &str) -> Option<i64> {
+    let (_, id) = split_kind(id);
+    if id.is_empty() {
+        return None;
+    }
+
+    id.bytes().try_fold(0i64, |value, byte| {
+        let digit = DIGITS
+            .iter()
+            .position(|d| *d == byte.to_ascii_lowercase())?;
+        value.checked_mul(36)?.checked_add(digit as i64)
+    })
+}
+
+/// Encode a numeric id, without kind prefix.
+pub fn encode(mut value: u64) -> String {
+    let mut digits = Vec::new();
+    loop {
+        digits.push(DIGITS[(value % 36) as usize]);
+        value /= 36;
+        if value == 0 {
+            break;
+        }
+    }
+
+    digits.iter().rev().map(|digit| *digit as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_kind_prefixes() {
+        assert_eq!(split_kind("t1_abc"), (Some("t1"), "abc"));
+        assert_eq!(split_kind("t3_abc"), (Some("t3"), "abc"));
+        assert_eq!(split_kind("abc"), (None, "abc"));
+        assert_eq!(split_kind("tx_abc"), (None, "tx_abc"));
+        assert_eq!(split_kind("T3_abc"), (None, "T3_abc"));
+        assert_eq!(split_kind("t3_"), (Some("t3"), ""));
+    }
+
+    #[test]
+    fn decode_ids() {
+        assert_eq!(decode("0"), Some(0));
+        assert_eq!(decode("z"), Some(35));
+        assert_eq!(decode("10"), Some(36));
+        assert_eq!(decode("t3_abc"), Some(13368));
+        assert_eq!(decode("t1_abc"), decode("abc"));
+        assert_eq!(decode("ABC"), Some(13368));
+        assert_eq!(decode(""), None);
+        assert_eq!(decode("t3_"), None);
+        assert_eq!(decode("a-b"), None);
+        assert_eq!(decode("unknown-5"), None);
+        assert_eq!(decode("tx_abc"), None);
+    }
+
+    #[test]
+    fn decode_overflow() {
+        assert_eq!(decode("1y2p0ij32e8e7"), Some(i64::MAX));
+        assert_eq!(decode("1y2p0ij32e8e8"), None);
+        assert_eq!(decode("zzzzzzzzzzzzz"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        for value in [0, 1, 35, 36, 13368, 1_000_000_007, i64::MAX as u64] {
+            let id = encode(value);
+            assert_eq!(decode(&id), Some(value as i64));
+            assert_eq!(decode(&format!("t1_{}", id)), Some(value as i64));
+            assert_eq!(decode(&format!("t3_{}", id)), Some(value as i64));
+        }
+        assert_eq!(encode(0), "0");
+        assert_eq!(encode(13368), "abc");
+    }
+}
@@ -1,4 +1,5 @@
-use serde_json;
+use crate::service::{ErrorPolicy, FilterCombine, VerifyPolicy};
+use std::collections::HashMap;
 
 #[derive(serde::Deserialize)]
 pub struct DatabaseConfig {
@@ -13,12 +14,139 @@ pub struct DatabaseConfig {
 pub struct Ingestion {
     pub push_summarized_db: bool,
     pub qsize_jobs_done: u64,
+    // Store comments in the wide `comments_full` table instead of `comments`.
+    #[serde(default)]
+    pub full_comments: bool,
+    // Build the comment trees at the end of the run.
+    #[serde(default)]
+    pub build_trees: bool,
+    #[serde(default)]
+    pub verify_policy: VerifyPolicy,
+}
+
+/// How to handle lines that are not valid UTF-8.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Utf8Mode {
+    /// Reject the line.
+    #[default]
+    Strict,
+    /// Replace the invalid sequences with U+FFFD and parse the line.
+    Lossy,
 }
 
 #[derive(serde::Deserialize)]
 pub struct Parser {
-    pub num_workers: u64,
+    #[serde(default = "default_parse_workers")]
+    pub parse_workers: u64,
     pub qsize_to_parse: u64,
+    #[serde(alias = "num_workers")]
+    pub db_workers: u64,
+    #[serde(default = "default_qsize_to_insert")]
+    pub qsize_to_insert: u64,
+    #[serde(default)]
+    pub utf8_mode: Utf8Mode,
+    #[serde(default = "default_rejected_lines_file")]
+    pub rejected_lines_file: String,
+    #[serde(default)]
+    pub error_policy: ErrorPolicy,
+}
+
+fn default_parse_workers() -> u64 {
+    std::thread::available_parallelism().map_or(4, |n| n.get() as u64)
+}
+
+fn default_qsize_to_insert() -> u64 {
+    1000
+}
+
+fn default_rejected_lines_file() -> String {
+    "rejected.txt".to_string()
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct Discovery {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub follow_symlinks: bool,
+    pub max_depth: usize,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            include: vec!["**/RC_*.zst".to_string(), "**/RS_*.zst".to_string()],
+            exclude: vec![],
+            follow_symlinks: false,
+            max_depth: usize::MAX,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct ContentFilterConfig {
+    pub keywords: Vec<String>,
+    pub regexes: Vec<String>,
+    pub case_insensitive: bool,
+    pub word_boundaries: bool,
+}
+
+impl Default for ContentFilterConfig {
+    fn default() -> Self {
+        ContentFilterConfig {
+            keywords: vec![],
+            regexes: vec![],
+            case_insensitive: true,
+            word_boundaries: true,
+        }
+    }
+}
+
+/// Thresholds for one kind of record. Unset limits are not checked.
+#[derive(serde::Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ThresholdRules {
+    pub min_score: Option<i64>,
+    /// Only applies to submissions.
+    pub min_num_comments: Option<i64>,
+    pub skip_deleted_authors: bool,
+    pub skip_deleted_bodies: bool,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+pub struct ThresholdConfig {
+    pub submissions: ThresholdRules,
+    pub comments: ThresholdRules,
+}
+
+/// Columns to store in a table. Empty lists select every column.
+#[derive(serde::Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ColumnSelection {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+/// Options of the `complete-context` pass.
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct ContextConfig {
+    /// How many months before a comment to look for its submission or parent.
+    pub lookback_months: u32,
+    /// Also import the missing parent comments, up to the top of the threads.
+    pub parent_comments: bool,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        ContextConfig {
+            lookback_months: 6,
+            parent_comments: false,
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -26,10 +154,39 @@ pub struct Config {
     pub target_folder: String,
     pub log_frequency: u64,
     pub subreddit_list: Vec<String>,
+    #[serde(default)]
+    pub subreddit_exclude: Vec<String>,
+    #[serde(default)]
+    pub author_list: Vec<String>,
+    #[serde(default)]
+    pub author_list_file: Option<String>,
+    #[serde(default)]
+    pub content_filter: ContentFilterConfig,
+    #[serde(default)]
+    pub filter_combine: FilterCombine,
+    #[serde(default)]
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub thresholds: ThresholdConfig,
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: f64,
+    #[serde(default)]
+    pub sample_by_thread: bool,
     pub log_file: String,
     pub database: DatabaseConfig,
     pub ingestion: Ingestion,
     pub parser: Parser,
+    #[serde(default)]
+    pub discovery: Discovery,
+    #[serde(default)]
+    pub context: ContextConfig,
+    // Column selection, keyed by table name.
+    #[serde(default)]
+    pub columns: HashMap<String, ColumnSelection>,
+}
+
+fn default_sample_rate() -> f64 {
+    1.0
 }
 
 pub fn read_json_config() -> Config {
@@ -37,3 +194,25 @@ pub fn read_json_config() -> Config {
     let config: Config = serde_json::from_str(&config).unwrap();
     config
 }
+
+impl Config {
+    /// Get the inline author list together with the authors listed in
+    /// `author_list_file`, one per line.
+    pub fn authors(&self) -> Vec<String> {
+        let mut authors = self.author_list.clone();
+
+        if let Some(path) = &self.author_list_file {
+            let contents = std::fs::read_to_string(path)
+                .unwrap_or_else(|err| panic!("Failed to read {}: {}", path, err));
+            authors.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+
+        authors
+    }
+}
@@ -0,0 +1,51 @@
+use crate::service::cfg::ColumnSelection;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// Columns selected per table, set once at startup.
+static SELECTION: OnceLock<HashMap<String, ColumnSelection>> = OnceLock::new();
+
+/// Set the columns to store per table. Tables that are not listed keep all
+/// their columns. Panics on an unknown table name.
+pub fn set_column_selection(selection: &HashMap<String, ColumnSelection>, tables: &[&str]) {
+    for table in selection.keys() {
+        if !tables.contains(&table.as_str()) {
+            panic!(
+                "Unknown table {} in columns, expected one of: {}",
+                table,
+                tables.join(", ")
+            );
+        }
+    }
+
+    SELECTION
+        .set(selection.clone())
+        .unwrap_or_else(|_| panic!("Column selection already set"));
+}
+
+/// Reduce the columns of a table to the selected ones. `id` is always kept,
+/// it is the primary key. Panics on an unknown column name.
+pub fn select_columns(
+    table: &str,
+    sql_types: Vec<(&'static str, &'static str)>,
+) -> Vec<(&'static str, &'static str)> {
+    let Some(selection) = SELECTION.get().and_then(|selection| selection.get(table)) else {
+        return sql_types;
+    };
+
+    for name in selection.include.iter().chain(&selection.exclude) {
+        if !sql_types.iter().any(|(column, _)| column == name) {
+            panic!("Unknown column {} in columns.{}", name, table);
+        }
+    }
+
+    let listed = |names: &[String], column: &str| names.iter().any(|name| name == column);
+    sql_types
+        .into_iter()
+        .filter(|(column, _)| {
+            *column == "id"
+                || ((selection.include.is_empty() || listed(&selection.include, column))
+                    && !listed(&selection.exclude, column))
+        })
+        .collect()
+}
@@ -0,0 +1,169 @@
+use crate::service::cfg::ContentFilterConfig;
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use regex::{Regex, RegexBuilder};
+
+/// Keyword and regex rules on the text of a record: submission titles and
+/// self texts, comment bodies.
+pub struct ContentFilter {
+    keywords: Vec<String>,
+    keyword_matcher: AhoCorasick,
+    // Matches the keywords in the raw JSON line, ignoring word boundaries.
+    raw_matcher: Option<AhoCorasick>,
+    regexes: Vec<Regex>,
+    case_insensitive: bool,
+    word_boundaries: bool,
+}
+
+// Check if a character is part of a word.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Check if a keyword is written the same in a JSON string.
+fn is_raw(keyword: &str) -> bool {
+    keyword
+        .chars()
+        .all(|c| c.is_ascii() && c != '"' && c != '\\' && c >= ' ')
+}
+
+impl ContentFilter {
+    pub fn new(config: &ContentFilterConfig) -> ContentFilter {
+        let keywords: Vec<String> = if config.case_insensitive {
+            config.keywords.iter().map(|k| k.to_lowercase()).collect()
+        } else {
+            config.keywords.clone()
+        };
+
+        let keyword_matcher = AhoCorasick::new(&keywords)
+            .unwrap_or_else(|err| panic!("Failed to build keyword matcher: {}", err));
+
+        // ASCII keywords without characters escaped by JSON appear as is in
+        // the line, so the line can be checked before it is deserialized.
+        // Regexes cannot.
+        let raw_matcher =
+            (config.regexes.is_empty() && keywords.iter().all(|k| is_raw(k))).then(|| {
+                AhoCorasickBuilder::new()
+                    .ascii_case_insensitive(config.case_insensitive)
+                    .build(&keywords)
+                    .unwrap_or_else(|err| panic!("Failed to build keyword matcher: {}", err))
+            });
+
+        let regexes = config
+            .regexes
+            .iter()
+            .map(|pattern| {
+                let pattern = if config.word_boundaries {
+                    format!(r"\b(?:{})\b", pattern)
+                } else {
+                    pattern.clone()
+                };
+
+                RegexBuilder::new(&pattern)
+                    .case_insensitive(config.case_insensitive)
+                    .build()
+                    .unwrap_or_else(|err| panic!("Invalid regex pattern {}: {}", pattern, err))
+            })
+            .collect();
+
+        ContentFilter {
+            keywords,
+            keyword_matcher,
+            raw_matcher,
+            regexes,
+            case_insensitive: config.case_insensitive,
+            word_boundaries: config.word_boundaries,
+        }
+    }
+
+    /// Check if the filter has any rules.
+    pub fn is_empty(&self) -> bool {
+        self.keywords.is_empty() && self.regexes.is_empty()
+    }
+
+    /// Cheap check of a raw JSON line.
+    ///
+    /// Returns false only when none of the keywords can be in the line.
+    pub fn may_match_raw(&self, line: &str) -> bool {
+        match &self.raw_matcher {
+            Some(matcher) => matcher.is_match(line),
+            None => true,
+        }
+    }
+
+    /// Find the keywords and regex matches in the given texts.
+    ///
+    /// Keywords are returned as configured, regex matches as the matched
+    /// text. Each term is returned once.
+    pub fn find_terms(&self, texts: &[&str]) -> Vec<String> {
+        let mut terms: Vec<String> = Vec::new();
+
+        for text in texts {
+            let folded;
+            let text = if self.case_insensitive {
+                folded = text.to_lowercase();
+                folded.as_str()
+            } else {
+                text
+            };
+
+            for found in self.keyword_matcher.find_overlapping_iter(text) {
+                if self.word_boundaries {
+                    let before = text[..found.start()].chars().next_back();
+                    let after = text[found.end()..].chars().next();
+                    if before.is_some_and(is_word_char) || after.is_some_and(is_word_char) {
+                        continue;
+                    }
+                }
+
+                let keyword = &self.keywords[found.pattern().as_usize()];
+                if !terms.contains(keyword) {
+                    terms.push(keyword.clone());
+                }
+            }
+
+            for regex in &self.regexes {
+                for found in regex.find_iter(text) {
+                    let term = found.as_str().to_string();
+                    if !terms.contains(&term) {
+                        terms.push(term);
+                    }
+                }
+            }
+        }
+
+        terms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(keywords: &[&str]) -> ContentFilter {
+        ContentFilter::new(&ContentFilterConfig {
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            word_boundaries: false,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn raw_check_skips_lines_without_keywords() {
+        let filter = filter(&["ferris", "Cargo"]);
+        assert!(filter.may_match_raw(r#"{"body": "I met FERRIS today"}"#));
+        assert!(filter.may_match_raw(r#"{"body": "cargo build"}"#));
+        assert!(!filter.may_match_raw(r#"{"body": "nothing here"}"#));
+    }
+
+    #[test]
+    fn raw_check_keeps_escaped_keywords() {
+        for keyword in [r#"say "hi""#, r"C:\temp", "tab\there"] {
+            let filter = filter(&[keyword]);
+            let body = format!("I wrote {} today", keyword);
+            let line = serde_json::json!({ "body": body }).to_string();
+            assert!(!line.contains(keyword), "{} is not escaped", keyword);
+            assert!(filter.may_match_raw(&line), "{}", keyword);
+            assert_eq!(filter.find_terms(&[&body]), vec![keyword.to_lowercase()]);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::service::base36;
+use crate::service::cfg::ContextConfig;
+use crate::service::db_object::DBObject;
+use crate::service::error::Result;
+use crate::service::prefilter::may_have_id;
+use crate::service::raw::{wait_zstd, zstd_command};
+use crate::service::raw_comment::RedditComment;
+use crate::service::{
+    DBRedditComment, DBRedditCommentFull, DBRedditSubmission, DBTable, ErrorPolicy, InsertStrategy,
+    RedditSubmission,
+};
+use chrono::Datelike;
+use regex::Regex;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+// Tells which filter selected the rows imported by this pass.
+const MATCHED_BY: &str = "context";
+
+// Months are counted from year 0, to compare and subtract them.
+fn month_index(year: i32, month: u32) -> i32 {
+    year * 12 + month as i32 - 1
+}
+
+// Month of a dump file, from a name such as `RS_2015-06.zst`.
+fn file_month(fname: &str, pattern: &Regex) -> Option<i32> {
+    let name = Path::new(fname).file_name()?.to_str()?;
+    let captures = pattern.captures(name)?;
+    let year = captures[1].parse().ok()?;
+    let month = captures[2].parse().ok()?;
+    Some(month_index(year, month))
+}
+
+// Group the missing ids by the months that may hold them: the month of the
+// earliest record pointing at them, and `lookback_months` before it.
+fn ids_by_month(
+    missing: &[(i64, Option<DateTime<Utc>>)],
+    lookback_months: u32,
+) -> HashMap<i32, HashSet<i64>> {
+    let mut months: HashMap<i32, HashSet<i64>> = HashMap::new();
+    for (id, created_utc) in missing {
+        let Some(created_utc) = created_utc else {
+            log::warn!(
+                "No creation time to look for id {}",
+                base36::encode(*id as u64)
+            );
+            continue;
+        };
+
+        let last = month_index(created_utc.year(), created_utc.month());
+        for month in last - lookback_months as i32..=last {
+            months.entry(month).or_default().insert(*id);
+        }
+    }
+    months
+}
+
+// Read the lines of a dump that may hold one of `ids`.
+async fn scan_file(fname: &str, ids: &HashSet<i64>) -> Vec<String> {
+    log::info!("[{}] Looking for {} ids", fname, ids.len());
+
+    let mut child = zstd_command(fname)
+        .spawn()
+        .expect("Failed to spawn zstd process");
+    let stdout = child.stdout.take().expect("Failed to open stdout");
+    let mut reader = BufReader::new(stdout);
+
+    let mut lines = Vec::new();
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                log::error!("[{}] Read error: {}", fname, err);
+                break;
+            }
+        }
+
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_start_matches(char::from(0)).trim_end();
+        if may_have_id(line, ids) {
+            lines.push(line.to_string());
+        }
+    }
+
+    wait_zstd(fname, child).await;
+
+    lines
+}
+
+// Find and import the records with the given ids from the dumps of `prefix`,
+// `RS_` or `RC_`. Returns the ids found.
+async fn import_missing(
+    pool: &PgPool,
+    files: &[String],
+    prefix: &str,
+    missing: &[(i64, Option<DateTime<Utc>>)],
+    config: &ContextConfig,
+    full_comments: bool,
+    policy: ErrorPolicy,
+) -> HashSet<i64> {
+    let pattern = Regex::new(&format!(r"^{}.*?(\d{{4}})-(\d{{2}})", prefix)).unwrap();
+    let months = ids_by_month(missing, config.lookback_months);
+
+    let mut found = HashSet::new();
+    for fname in files {
+        let Some(month) = file_month(fname, &pattern) else {
+            continue;
+        };
+        let Some(ids) = months.get(&month) else {
+            continue;
+        };
+        let ids: HashSet<i64> = ids.difference(&found).copied().collect();
+        if ids.is_empty() {
+            continue;
+        }
+
+        for line in scan_file(fname, &ids).await {
+            let imported = if prefix == "RS_" {
+                import_submission(pool, &line, &ids, policy).await
+            } else {
+                import_comment(pool, &line, &ids, full_comments, policy).await
+            };
+
+            match imported {
+                Ok(Some(id)) => {
+                    found.insert(id);
+                }
+                Ok(None) => {}
+                Err(err) => log::error!("[{}] Failed to import a record: {}", fname, err),
+            }
+        }
+    }
+
+    found
+}
+
+// Import a submission line if its id is one of `ids`.
+async fn import_submission(
+    pool: &PgPool,
+    line: &str,
+    ids: &HashSet<i64>,
+    policy: ErrorPolicy,
+) -> Result<Option<i64>> {
+    let submission = RedditSubmission::try_from(line)?;
+    let Some(id) = base36::decode(&submission.id).filter(|id| ids.contains(id)) else {
+        return Ok(None);
+    };
+
+    let mut db_submission = DBRedditSubmission::new(&submission, policy)?;
+    db_submission.matched_by = MATCHED_BY.to_string();
+    db_submission
+        .insert(pool, InsertStrategy::InsertKeepContent, false)
+        .await?;
+    Ok(Some(id))
+}
+
+// Import a comment line if its id is one of `ids`.
+async fn import_comment(
+    pool: &PgPool,
+    line: &str,
+    ids: &HashSet<i64>,
+    full_comments: bool,
+    policy: ErrorPolicy,
+) -> Result<Option<i64>> {
+    let comment = RedditComment::try_from(line)?;
+    let Some(id) = base36::decode(&comment.id).filter(|id| ids.contains(id)) else {
+        return Ok(None);
+    };
+
+    if full_comments {
+        let mut db_comment = DBRedditCommentFull::new(&comment, policy)?;
+        db_comment.comment.matched_by = MATCHED_BY.to_string();
+        db_comment
+            .insert(pool, InsertStrategy::InsertKeepContent, false)
+            .await?;
+    } else {
+        let mut db_comment = DBRedditComment::new(&comment, policy)?;
+        db_comment.matched_by = MATCHED_BY.to_string();
+        db_comment
+            .insert(pool, InsertStrategy::InsertKeepContent, false)
+            .await?;
+    }
+    Ok(Some(id))
+}
+
+// Run a query listing missing ids with the creation time of the earliest
+// record pointing at them.
+async fn fetch_missing(pool: &PgPool, sql: &str) -> Vec<(i64, Option<DateTime<Utc>>)> {
+    sqlx::query_as(sql)
+        .fetch_all(pool)
+        .await
+        .unwrap_or_else(|err| panic!("Failed to list the missing records: {}", err))
+}
+
+/// Import the submissions of the imported comments that are missing from
+/// `submissions`, and with `parent_comments` their missing parent comments.
+///
+/// The records are looked for in the dumps of `files` from the month of the
+/// earliest comment pointing at them, going back `lookback_months`. They are
+/// imported regardless of the filters, with `context` in `matched_by`.
+pub async fn complete_context<T: DBTable>(
+    pool: &PgPool,
+    files: &[String],
+    config: &ContextConfig,
+    full_comments: bool,
+    policy: ErrorPolicy,
+) {
+    let comments = T::table_name();
+    let columns = ["id_num", "link_id_num", "parent_kind", "parent_id_num"];
+    for column in columns {
+        if !T::has_column(column) {
+            panic!(
+                "Column {} of {} is needed to complete the context",
+                column, comments
+            );
+        }
+    }
+    if !DBRedditSubmission::has_column("id_num") {
+        panic!(
+            "Column id_num of {} is needed to complete the context",
+            DBRedditSubmission::table_name()
+        );
+    }
+
+    let missing = fetch_missing(
+        pool,
+        &format!(
+            "SELECT c.link_id_num, MIN(c.created_utc) FROM {} AS c \
+            WHERE c.link_id_num IS NOT NULL \
+            AND NOT EXISTS (SELECT 1 FROM {} AS s WHERE s.id_num = c.link_id_num) \
+            GROUP BY c.link_id_num",
+            comments,
+            DBRedditSubmission::table_name()
+        ),
+    )
+    .await;
+    let found = import_missing(pool, files, "RS_", &missing, config, full_comments, policy).await;
+    log::info!(
+        "Imported {} of {} missing submissions",
+        found.len(),
+        missing.len()
+    );
+    println!(
+        "Imported {} of {} missing submissions",
+        found.len(),
+        missing.len()
+    );
+
+    if !config.parent_comments {
+        return;
+    }
+
+    // Each round imports one level of parents. Parents that were looked for
+    // and not found are not looked for again.
+    let mut searched: HashSet<i64> = HashSet::new();
+    let mut total_missing = 0;
+    let mut total_found = 0;
+    loop {
+        let missing: Vec<(i64, Option<DateTime<Utc>>)> = fetch_missing(
+            pool,
+            &format!(
+                "SELECT c.parent_id_num, MIN(c.created_utc) FROM {0} AS c \
+                WHERE c.parent_kind = 't1' AND c.parent_id_num IS NOT NULL \
+                AND NOT EXISTS (SELECT 1 FROM {0} AS p WHERE p.id_num = c.parent_id_num) \
+                GROUP BY c.parent_id_num",
+                comments
+            ),
+        )
+        .await
+        .into_iter()
+        .filter(|(id, _)| !searched.contains(id))
+        .collect();
+        if missing.is_empty() {
+            break;
+        }
+        searched.extend(missing.iter().map(|(id, _)| *id));
+
+        let found =
+            import_missing(pool, files, "RC_", &missing, config, full_comments, policy).await;
+        log::info!(
+            "Imported {} of {} missing parent comments",
+            found.len(),
+            missing.len()
+        );
+        total_missing += missing.len();
+        total_found += found.len();
+        if found.is_empty() {
+            break;
+        }
+    }
+
+    println!(
+        "Imported {} of {} missing parent comments",
+        total_found, total_missing
+    );
+}
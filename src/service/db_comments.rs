@@ -1,22 +1,93 @@
-use crate::service::db_object::DBObject;
-use crate::service::raw_comment::RedditComment;
+use crate::service::base36;
+use crate::service::db_object::{
+    create_id_indexes, fill_deleted_flags, fill_edited_flags, fill_id_nums, insert_row, DBObject,
+    SqlValue, DELETED, REMOVED,
+};
+use crate::service::error::{ErrorPolicy, Result};
+use crate::service::raw_comment::{ParentId, RedditComment};
 use crate::service::{DBTable, InsertStrategy};
 use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::PgPool;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Numeric ids decoded from the text ids, as (numeric column, text column).
+pub const ID_NUMS: [(&str, &str); 3] = [
+    ("id_num", "id"),
+    ("link_id_num", "link_id"),
+    ("parent_id_num", "parent_id"),
+];
+
+// Integer parent ids converted to fullnames, for the run report.
+static RESOLVED_PARENT_IDS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of comments whose integer parent id was converted since the start.
+pub fn resolved_parent_ids() -> u64 {
+    RESOLVED_PARENT_IDS.load(Ordering::Relaxed)
+}
+
+/// Migration converting the `unknown-N` parent ids stored by earlier
+/// versions for integer parent ids, like `ParentId::to_fullname`.
+pub fn resolve_parent_ids_sql<T: DBTable>() -> Option<String> {
+    if !T::has_column("parent_id") || !T::has_column("link_id") {
+        return None;
+    }
+
+    // Base 36 digits of the number, most significant first.
+    let encoded = "(SELECT string_agg(substr('0123456789abcdefghijklmnopqrstuvwxyz', \
+        mod(div(u.num, power(36::numeric, k)), 36)::integer + 1, 1), '' ORDER BY k DESC) \
+        FROM generate_series(0, 12) AS k WHERE k = 0 OR power(36::numeric, k) <= u.num)";
+    let is_root = "c.link_id IN ('t3_' || p.encoded, p.encoded)";
+
+    let mut sets = vec![format!(
+        "parent_id = CASE WHEN {} THEN 't3_' ELSE 't1_' END || p.encoded",
+        is_root
+    )];
+    if T::has_column("parent_kind") {
+        sets.push(format!(
+            "parent_kind = CASE WHEN {} THEN 't3' ELSE 't1' END",
+            is_root
+        ));
+    }
+    if T::has_column("parent_id_num") {
+        sets.push("parent_id_num = p.num::bigint".to_string());
+    }
+
+    Some(format!(
+        "UPDATE {0} AS c SET {1} FROM (SELECT u.id, u.num, {2} AS encoded FROM \
+        (SELECT id, substring(parent_id from 9)::numeric AS num FROM {0} \
+        WHERE parent_id ~ '^unknown-[0-9]{{1,18}}$') AS u) AS p WHERE c.id = p.id",
+        T::table_name(),
+        sets.join(", "),
+        encoded
+    ))
+}
 
 #[derive(Clone)]
 pub struct DBRedditComment {
     pub author: String,
     pub body: String,
-    pub created_utc: DateTime<Utc>,
-    pub edited: DateTime<Utc>,
+    pub created_utc: Option<DateTime<Utc>>,
+    pub edited: Option<DateTime<Utc>>,
+    pub is_edited: bool,
     pub id: String,
     pub link_id: String,
     pub parent_id: String,
     pub permalink: String,
-    pub retrieved_on: DateTime<Utc>,
+    pub retrieved_on: Option<DateTime<Utc>>,
     pub score: i64,
     pub subreddit: String,
+    pub matched_by: String,
+    pub matched_terms: Vec<String>,
+    pub author_deleted: bool,
+    pub body_deleted: bool,
+    pub body_removed: bool,
+    // Retrieval time of the first snapshot seen deleted or removed.
+    pub deleted_on: Option<DateTime<Utc>>,
+    pub id_num: Option<i64>,
+    pub link_id_num: Option<i64>,
+    // `t1` for replies, `t3` for top level comments.
+    pub parent_kind: Option<String>,
+    pub parent_id_num: Option<i64>,
 }
 
 impl DBTable for DBRedditComment {
@@ -30,6 +101,7 @@ impl DBTable for DBRedditComment {
             ("body", "text"),
             ("created_utc", "timestamp with time zone"),
             ("edited", "timestamp with time zone"),
+            ("is_edited", "boolean"),
             ("id", "text"),
             ("link_id", "text"),
             ("parent_id", "text"),
@@ -37,6 +109,22 @@ impl DBTable for DBRedditComment {
             ("retrieved_on", "timestamp with time zone"),
             ("score", "integer"),
             ("subreddit", "text"),
+            ("matched_by", "text"),
+            ("matched_terms", "text[]"),
+            ("author_deleted", "boolean"),
+            ("body_deleted", "boolean"),
+            ("body_removed", "boolean"),
+            ("deleted_on", "timestamp with time zone"),
+            ("id_num", "bigint"),
+            ("link_id_num", "bigint"),
+            ("parent_kind", "text"),
+            ("parent_id_num", "bigint"),
+            // Filled by `build_comment_trees`.
+            ("depth", "integer"),
+            ("root_comment_id", "text"),
+            ("path", "bigint[]"),
+            ("orphan", "boolean"),
+            ("missing_submission", "boolean"),
         ]
     }
 
@@ -48,6 +136,38 @@ impl DBTable for DBRedditComment {
         .execute(pool)
         .await
         .expect("Failed to add primary key.");
+
+        create_id_indexes::<Self>(pool, &ID_NUMS.map(|(num, _)| num)).await;
+    }
+
+    async fn post_add_columns(pool: &PgPool, added: &[&str]) {
+        if added.contains(&"author_deleted") {
+            fill_deleted_flags::<Self>(pool, "body").await;
+        }
+        if added.contains(&"is_edited") {
+            fill_edited_flags::<Self>(pool).await;
+        }
+        if added.contains(&"id_num") {
+            fill_id_nums::<Self>(pool, &ID_NUMS).await;
+            create_id_indexes::<Self>(pool, &ID_NUMS.map(|(num, _)| num)).await;
+        }
+    }
+
+    fn migrations() -> Vec<(&'static str, String)> {
+        let mut migrations = Vec::new();
+        if Self::has_column("edited") {
+            migrations.push((
+                "comments_edited_null",
+                format!(
+                    "UPDATE {} SET edited = NULL WHERE edited = to_timestamp(0)",
+                    Self::table_name()
+                ),
+            ));
+        }
+        if let Some(sql) = resolve_parent_ids_sql::<Self>() {
+            migrations.push(("comments_int_parent_ids", sql));
+        }
+        migrations
     }
 }
 
@@ -56,10 +176,15 @@ impl DBObject for DBRedditComment {
         "DBRedditComment"
     }
 
-    async fn insert(&self, pool: &PgPool, insert_strategy: InsertStrategy, skip_exists: bool) {
+    async fn insert(
+        &self,
+        pool: &PgPool,
+        insert_strategy: InsertStrategy,
+        skip_exists: bool,
+    ) -> Result<()> {
         // Skipping existing entries if needed
-        if insert_strategy == InsertStrategy::InsertUpdate && skip_exists {
-            panic!("Cannot skip exists and use InsertUpdate.");
+        if insert_strategy != InsertStrategy::InsertIgnore && skip_exists {
+            panic!("Cannot skip exists and update existing rows.");
         }
 
         if skip_exists {
@@ -67,71 +192,94 @@ impl DBObject for DBRedditComment {
             let exists = sqlx::query(&sql)
                 .bind(&self.id)
                 .fetch_optional(pool)
-                .await
-                .unwrap_or_else(|err| {
-                    panic!(
-                        "[DBRedditComment] Failed to check if comment exists: {}",
-                        err
-                    );
-                });
+                .await?;
 
             if exists.is_some() {
-                return;
+                return Ok(());
             }
         }
 
-        let mut sql = format!(
-            "INSERT INTO {} (author, body, created_utc, edited, id, link_id, parent_id, permalink, retrieved_on, score, subreddit) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
-            Self::table_name()
-        );
-
-        if insert_strategy == InsertStrategy::InsertIgnore {
-            sql.push_str(" ON CONFLICT DO NOTHING");
-        } else if insert_strategy == InsertStrategy::InsertUpdate {
-            panic!("InsertUpdate not implemented.");
-        } else {
-            panic!("Invalid insert strategy.");
-        }
-
-        sqlx::query(&sql)
-            .bind(&self.author)
-            .bind(&self.body)
-            .bind(self.created_utc)
-            .bind(self.edited)
-            .bind(&self.id)
-            .bind(&self.link_id)
-            .bind(&self.parent_id)
-            .bind(&self.permalink)
-            .bind(self.retrieved_on)
-            .bind(self.score)
-            .bind(&self.subreddit)
-            .execute(pool)
-            .await
-            .unwrap_or_else(|err| {
-                panic!("[DBRedditComment] Failed to insert comment: {}", err);
-            });
+        insert_row::<Self>(pool, insert_strategy, "body", &["is_edited"], self.values()).await
     }
 }
 
-// Convert from RedditComment to DBRedditComment
-impl From<&RedditComment> for DBRedditComment {
-    fn from(comment: &RedditComment) -> Self {
-        DBRedditComment {
+impl DBRedditComment {
+    /// Values of the columns, in the order of `sql_types`.
+    pub fn values(&self) -> Vec<(&'static str, SqlValue<'_>)> {
+        vec![
+            ("author", SqlValue::Text(Some(&self.author))),
+            ("body", SqlValue::Text(Some(&self.body))),
+            ("created_utc", SqlValue::Timestamp(self.created_utc)),
+            ("edited", SqlValue::Timestamp(self.edited)),
+            ("is_edited", SqlValue::Bool(Some(self.is_edited))),
+            ("id", SqlValue::Text(Some(&self.id))),
+            ("link_id", SqlValue::Text(Some(&self.link_id))),
+            ("parent_id", SqlValue::Text(Some(&self.parent_id))),
+            ("permalink", SqlValue::Text(Some(&self.permalink))),
+            ("retrieved_on", SqlValue::Timestamp(self.retrieved_on)),
+            ("score", SqlValue::Int(Some(self.score))),
+            ("subreddit", SqlValue::Text(Some(&self.subreddit))),
+            ("matched_by", SqlValue::Text(Some(&self.matched_by))),
+            ("matched_terms", SqlValue::TextArray(&self.matched_terms)),
+            ("author_deleted", SqlValue::Bool(Some(self.author_deleted))),
+            ("body_deleted", SqlValue::Bool(Some(self.body_deleted))),
+            ("body_removed", SqlValue::Bool(Some(self.body_removed))),
+            ("deleted_on", SqlValue::Timestamp(self.deleted_on)),
+            ("id_num", SqlValue::Int(self.id_num)),
+            ("link_id_num", SqlValue::Int(self.link_id_num)),
+            ("parent_kind", SqlValue::Text(self.parent_kind.as_deref())),
+            ("parent_id_num", SqlValue::Int(self.parent_id_num)),
+        ]
+    }
+
+    /// Convert from RedditComment, applying `policy` to the values that
+    /// cannot be converted.
+    pub fn new(comment: &RedditComment, policy: ErrorPolicy) -> Result<Self> {
+        let author_deleted = comment.author == DELETED || comment.author == REMOVED;
+        let body_deleted = comment.body == DELETED;
+        let body_removed = comment.body == REMOVED;
+        let retrieved_on = comment.retrieved_on.to_datetime(policy)?;
+        let parent_id = match &comment.parent_id {
+            Some(parent_id) => match parent_id.to_fullname(&comment.link_id) {
+                Ok(parent_id) => parent_id,
+                Err(_) if policy == ErrorPolicy::Null => String::new(),
+                Err(err) => return Err(err),
+            },
+            None => String::new(),
+        };
+        if matches!(comment.parent_id, Some(ParentId::Int(_))) && !parent_id.is_empty() {
+            RESOLVED_PARENT_IDS.fetch_add(1, Ordering::Relaxed);
+        }
+        let parent_kind = base36::split_kind(&parent_id).0.map(str::to_string);
+
+        Ok(DBRedditComment {
             author: comment.author.clone(),
             body: comment.body.clone(),
-            created_utc: DateTime::<Utc>::from(&comment.created_utc),
-            edited: DateTime::<Utc>::from(&comment.edited),
+            created_utc: comment.created_utc.to_datetime(policy)?,
+            edited: comment.edited.to_edited_datetime(policy)?,
+            is_edited: comment.edited.is_edited(),
             id: comment.id.clone(),
             link_id: comment.link_id.clone(),
-            parent_id: comment
-                .parent_id
-                .as_ref()
-                .map_or_else(String::new, |id| id.into()),
+            id_num: base36::decode(&comment.id),
+            link_id_num: base36::decode(&comment.link_id),
+            parent_kind,
+            parent_id_num: base36::decode(&parent_id),
+            parent_id,
             permalink: comment.permalink.clone().unwrap_or_default(),
-            retrieved_on: DateTime::<Utc>::from(&comment.retrieved_on),
+            retrieved_on,
             score: comment.score,
             subreddit: comment.subreddit.clone(),
-        }
+            matched_by: String::new(),
+            matched_terms: vec![],
+            author_deleted,
+            body_deleted,
+            body_removed,
+            deleted_on: if author_deleted || body_deleted || body_removed {
+                retrieved_on
+            } else {
+                None
+            },
+        })
     }
 }
 
@@ -0,0 +1,236 @@
+use crate::service::db_comments::{resolve_parent_ids_sql, ID_NUMS};
+use crate::service::db_object::{
+    create_id_indexes, fill_deleted_flags, fill_edited_flags, fill_id_nums, insert_row, DBObject,
+    SqlValue,
+};
+use crate::service::error::{ErrorPolicy, Result};
+use crate::service::raw_comment::RedditComment;
+use crate::service::raw_object::AnyTimestamp;
+use crate::service::{DBRedditComment, DBTable, InsertStrategy};
+use serde_json::Value;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+/// A comment with the columns of `comments` and most of the other fields of
+/// the dumps, for the `comments_full` table.
+#[derive(Clone)]
+pub struct DBRedditCommentFull {
+    pub comment: DBRedditComment,
+    pub all_awardings: Option<Value>,
+    pub archived: Option<bool>,
+    pub author_cakeday: Option<bool>,
+    pub author_flair_css_class: Option<String>,
+    pub author_flair_text: Option<String>,
+    pub author_fullname: Option<String>,
+    pub author_premium: Option<bool>,
+    pub can_gild: Option<bool>,
+    pub collapsed: Option<bool>,
+    pub collapsed_reason: Option<String>,
+    pub collapsed_reason_code: Option<String>,
+    pub comment_type: Option<String>,
+    pub controversiality: Option<i64>,
+    pub distinguished: Option<String>,
+    pub downs: Option<i64>,
+    pub gilded: i64,
+    pub gildings: Option<Value>,
+    pub is_submitter: Option<bool>,
+    pub locked: Option<bool>,
+    pub no_follow: Option<bool>,
+    pub score_hidden: Option<bool>,
+    pub send_replies: Option<bool>,
+    pub stickied: Option<bool>,
+    pub subreddit_id: String,
+    pub subreddit_type: Option<String>,
+    pub total_awards_received: Option<i64>,
+    pub ups: Option<i64>,
+    pub updated_on: Option<DateTime<Utc>>,
+}
+
+impl DBTable for DBRedditCommentFull {
+    fn table_name() -> &'static str {
+        "comments_full"
+    }
+
+    fn sql_types() -> Vec<(&'static str, &'static str)> {
+        let mut types = DBRedditComment::sql_types();
+        types.extend([
+            ("all_awardings", "jsonb"),
+            ("archived", "boolean"),
+            ("author_cakeday", "boolean"),
+            ("author_flair_css_class", "text"),
+            ("author_flair_text", "text"),
+            ("author_fullname", "text"),
+            ("author_premium", "boolean"),
+            ("can_gild", "boolean"),
+            ("collapsed", "boolean"),
+            ("collapsed_reason", "text"),
+            ("collapsed_reason_code", "text"),
+            ("comment_type", "text"),
+            ("controversiality", "integer"),
+            ("distinguished", "text"),
+            ("downs", "integer"),
+            ("gilded", "integer"),
+            ("gildings", "jsonb"),
+            ("is_submitter", "boolean"),
+            ("locked", "boolean"),
+            ("no_follow", "boolean"),
+            ("score_hidden", "boolean"),
+            ("send_replies", "boolean"),
+            ("stickied", "boolean"),
+            ("subreddit_id", "text"),
+            ("subreddit_type", "text"),
+            ("total_awards_received", "integer"),
+            ("ups", "integer"),
+            ("updated_on", "timestamp with time zone"),
+        ]);
+        types
+    }
+
+    async fn post_create_table(pool: &PgPool) {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD PRIMARY KEY (id)",
+            Self::table_name()
+        ))
+        .execute(pool)
+        .await
+        .expect("Failed to add primary key.");
+
+        create_id_indexes::<Self>(pool, &ID_NUMS.map(|(num, _)| num)).await;
+    }
+
+    async fn post_add_columns(pool: &PgPool, added: &[&str]) {
+        if added.contains(&"author_deleted") {
+            fill_deleted_flags::<Self>(pool, "body").await;
+        }
+        if added.contains(&"is_edited") {
+            fill_edited_flags::<Self>(pool).await;
+        }
+        if added.contains(&"id_num") {
+            fill_id_nums::<Self>(pool, &ID_NUMS).await;
+            create_id_indexes::<Self>(pool, &ID_NUMS.map(|(num, _)| num)).await;
+        }
+    }
+
+    fn migrations() -> Vec<(&'static str, String)> {
+        match resolve_parent_ids_sql::<Self>() {
+            Some(sql) => vec![("comments_full_int_parent_ids", sql)],
+            None => vec![],
+        }
+    }
+}
+
+impl DBObject for DBRedditCommentFull {
+    fn type_name() -> &'static str {
+        "DBRedditCommentFull"
+    }
+
+    async fn insert(
+        &self,
+        pool: &PgPool,
+        insert_strategy: InsertStrategy,
+        skip_exists: bool,
+    ) -> Result<()> {
+        if skip_exists {
+            panic!("Cannot skip exists for {}.", Self::table_name());
+        }
+
+        insert_row::<Self>(pool, insert_strategy, "body", &["is_edited"], self.values()).await
+    }
+}
+
+impl DBRedditCommentFull {
+    /// Values of the columns, in the order of `sql_types`.
+    pub fn values(&self) -> Vec<(&'static str, SqlValue<'_>)> {
+        let mut values = self.comment.values();
+        values.extend([
+            ("all_awardings", SqlValue::Json(self.all_awardings.as_ref())),
+            ("archived", SqlValue::Bool(self.archived)),
+            ("author_cakeday", SqlValue::Bool(self.author_cakeday)),
+            (
+                "author_flair_css_class",
+                SqlValue::Text(self.author_flair_css_class.as_deref()),
+            ),
+            (
+                "author_flair_text",
+                SqlValue::Text(self.author_flair_text.as_deref()),
+            ),
+            (
+                "author_fullname",
+                SqlValue::Text(self.author_fullname.as_deref()),
+            ),
+            ("author_premium", SqlValue::Bool(self.author_premium)),
+            ("can_gild", SqlValue::Bool(self.can_gild)),
+            ("collapsed", SqlValue::Bool(self.collapsed)),
+            (
+                "collapsed_reason",
+                SqlValue::Text(self.collapsed_reason.as_deref()),
+            ),
+            (
+                "collapsed_reason_code",
+                SqlValue::Text(self.collapsed_reason_code.as_deref()),
+            ),
+            ("comment_type", SqlValue::Text(self.comment_type.as_deref())),
+            ("controversiality", SqlValue::Int(self.controversiality)),
+            (
+                "distinguished",
+                SqlValue::Text(self.distinguished.as_deref()),
+            ),
+            ("downs", SqlValue::Int(self.downs)),
+            ("gilded", SqlValue::Int(Some(self.gilded))),
+            ("gildings", SqlValue::Json(self.gildings.as_ref())),
+            ("is_submitter", SqlValue::Bool(self.is_submitter)),
+            ("locked", SqlValue::Bool(self.locked)),
+            ("no_follow", SqlValue::Bool(self.no_follow)),
+            ("score_hidden", SqlValue::Bool(self.score_hidden)),
+            ("send_replies", SqlValue::Bool(self.send_replies)),
+            ("stickied", SqlValue::Bool(self.stickied)),
+            ("subreddit_id", SqlValue::Text(Some(&self.subreddit_id))),
+            (
+                "subreddit_type",
+                SqlValue::Text(self.subreddit_type.as_deref()),
+            ),
+            (
+                "total_awards_received",
+                SqlValue::Int(self.total_awards_received),
+            ),
+            ("ups", SqlValue::Int(self.ups)),
+            ("updated_on", SqlValue::Timestamp(self.updated_on)),
+        ]);
+        values
+    }
+
+    /// Convert from RedditComment, see `DBRedditComment::new`.
+    pub fn new(comment: &RedditComment, policy: ErrorPolicy) -> Result<Self> {
+        Ok(DBRedditCommentFull {
+            comment: DBRedditComment::new(comment, policy)?,
+            all_awardings: comment.all_awardings.clone().map(Value::Array),
+            archived: comment.archived,
+            author_cakeday: comment.author_cakeday,
+            author_flair_css_class: comment.author_flair_css_class.clone(),
+            author_flair_text: comment.author_flair_text.clone(),
+            author_fullname: comment.author_fullname.clone(),
+            author_premium: comment.author_premium,
+            can_gild: comment.can_gild,
+            collapsed: comment.collapsed,
+            collapsed_reason: comment.collapsed_reason.clone(),
+            collapsed_reason_code: comment.collapsed_reason_code.clone(),
+            comment_type: comment.comment_type.clone(),
+            controversiality: comment.controversiality,
+            distinguished: comment.distinguished.clone(),
+            downs: comment.downs,
+            gilded: comment.gilded,
+            gildings: comment.gildings.clone(),
+            is_submitter: comment.is_submitter,
+            locked: comment.locked,
+            no_follow: comment.no_follow,
+            score_hidden: comment.score_hidden,
+            send_replies: comment.send_replies,
+            stickied: comment.stickied,
+            subreddit_id: comment.subreddit_id.clone(),
+            subreddit_type: comment.subreddit_type.clone(),
+            total_awards_received: comment.total_awards_received,
+            ups: comment.ups,
+            updated_on: AnyTimestamp::to_optional_datetime(comment.updated_on.as_ref(), policy)?,
+        })
+    }
+}
@@ -3,14 +3,32 @@ use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use sqlx::Row;
 
+// Compare a type from information_schema with a type from `sql_types()`.
+// Arrays are all reported as `ARRAY` by information_schema.
+fn same_type(data_type: &str, expected_type: &str) -> bool {
+    if expected_type.ends_with("[]") {
+        return data_type == "ARRAY";
+    }
+
+    data_type == expected_type
+}
+
+// Names of the data migrations already applied.
+const MIGRATIONS_TABLE: &str = "migrations";
+
 pub struct DBManager {
     pub pool: PgPool,
 }
 
 #[derive(PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum InsertStrategy {
     InsertIgnore,
+    #[allow(dead_code)]
     InsertUpdate,
+    // Insert new rows. For existing rows, only record that the author or the
+    // text was deleted, keeping the content of the earlier snapshot.
+    InsertKeepContent,
 }
 
 impl DBManager {
@@ -52,7 +70,7 @@ impl DBManager {
             }
 
             sql.push_str(name);
-            sql.push_str(" ");
+            sql.push(' ');
             sql.push_str(sql_type);
         }
 
@@ -64,6 +82,17 @@ impl DBManager {
             .expect("Failed to create table.");
     }
 
+    // Add a column to an existing table.
+    async fn add_column(&self, table_name: &str, name: &str, sql_type: &str) {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+            table_name, name, sql_type
+        ))
+        .execute(&self.pool)
+        .await
+        .expect("Failed to add column.");
+    }
+
     // Get the schema of a table.
     async fn get_schema(&self, table_name: &str) -> Vec<(String, String)> {
         let schema = sqlx::query(
@@ -114,7 +143,8 @@ impl DBManager {
 
     // Check if the table exists and create it if it doesn't.
     pub async fn check_tables<T: DBTable>(&self) {
-        let sql_types_sub = T::sql_types();
+        let sql_types_sub = T::columns();
+        let all_types = T::sql_types();
         let target_table = T::table_name();
 
         log::info!("Checking table {}", target_table);
@@ -132,7 +162,7 @@ impl DBManager {
             let mut found = false;
             for (expected_name, expected_type) in &sql_types_sub {
                 if name == *expected_name {
-                    if data_type != *expected_type {
+                    if !same_type(&data_type, expected_type) {
                         log::error!(
                             "Column {} has type {} but expected type {}.",
                             name,
@@ -146,6 +176,16 @@ impl DBManager {
                 }
             }
 
+            // Columns left out by the `columns` config stay as they are.
+            if !found
+                && all_types
+                    .iter()
+                    .any(|(expected_name, _)| name == *expected_name)
+            {
+                log::info!("Column {} is not selected, it is left empty.", name);
+                continue;
+            }
+
             // If the column is not found in the expected schema, panic.
             if !found {
                 log::error!("Column {} not found in expected schema.", name);
@@ -167,8 +207,9 @@ impl DBManager {
             }
         }
 
-        // Check for missing columns.
-        for (expected_name, _) in &sql_types_sub {
+        // Add missing columns.
+        let mut added = Vec::new();
+        for (expected_name, expected_type) in &sql_types_sub {
             let mut found = false;
             for (name, _) in schema_sub.clone() {
                 if name == *expected_name {
@@ -178,8 +219,58 @@ impl DBManager {
             }
 
             if !found {
-                log::error!("Column {} not found in table.", expected_name);
+                log::info!("Column {} not found in table, adding it.", expected_name);
+                self.add_column(target_table, expected_name, expected_type)
+                    .await;
+                added.push(*expected_name);
+            }
+        }
+
+        if !added.is_empty() {
+            T::post_add_columns(&self.pool, &added).await;
+        }
+
+        self.run_migrations::<T>().await;
+    }
+
+    // Run the migrations of a table that were not applied yet.
+    async fn run_migrations<T: DBTable>(&self) {
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (name text PRIMARY KEY, applied_on timestamp with time zone DEFAULT now())",
+            MIGRATIONS_TABLE
+        ))
+        .execute(&self.pool)
+        .await
+        .expect("Failed to create migrations table.");
+
+        for (name, sql) in T::migrations() {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .expect("Failed to start transaction.");
+
+            // Inserting the name first makes concurrent runs wait on each other.
+            let inserted = sqlx::query(&format!(
+                "INSERT INTO {} (name) VALUES ($1) ON CONFLICT DO NOTHING",
+                MIGRATIONS_TABLE
+            ))
+            .bind(name)
+            .execute(&mut *tx)
+            .await
+            .expect("Failed to record migration.");
+
+            if inserted.rows_affected() == 0 {
+                continue;
             }
+
+            log::info!("Running migration {}", name);
+            let result = sqlx::query(&sql)
+                .execute(&mut *tx)
+                .await
+                .unwrap_or_else(|err| panic!("Migration {} failed: {}", name, err));
+            tx.commit().await.expect("Failed to commit migration.");
+            log::info!("Migration {} updated {} rows", name, result.rows_affected());
         }
     }
 }
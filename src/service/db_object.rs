@@ -1,15 +1,318 @@
+use crate::service::columns::select_columns;
+use crate::service::error::Result;
 use crate::service::InsertStrategy;
-use sqlx::PgPool;
+use serde_json::Value;
+use sqlx::postgres::PgArguments;
+use sqlx::query::Query;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres};
+
+/// Text Reddit leaves in place of deleted authors and texts.
+pub const DELETED: &str = "[deleted]";
+/// Text Reddit leaves in place of texts removed by moderators.
+pub const REMOVED: &str = "[removed]";
+
+// Flags set when a snapshot of a record was seen deleted or removed.
+const DELETED_FLAGS: [&str; 3] = ["author_deleted", "body_deleted", "body_removed"];
 
 pub trait DBTable {
     fn table_name() -> &'static str;
     fn sql_types() -> Vec<(&'static str, &'static str)>;
     async fn post_create_table(pool: &PgPool);
+    // Fill the columns added to an existing table.
+    async fn post_add_columns(pool: &PgPool, added: &[&str]);
+    // Data migrations, as (name, sql). Each one runs once per database.
+    fn migrations() -> Vec<(&'static str, String)>;
+
+    // The columns stored, `sql_types` reduced by the `columns` config.
+    fn columns() -> Vec<(&'static str, &'static str)> {
+        select_columns(Self::table_name(), Self::sql_types())
+    }
+
+    fn has_column(name: &str) -> bool {
+        Self::columns().iter().any(|(column, _)| *column == name)
+    }
 }
 
 pub trait DBObject {
-    async fn insert(&self, pool: &PgPool, strategy: InsertStrategy, ignore: bool);
+    async fn insert(&self, pool: &PgPool, strategy: InsertStrategy, ignore: bool) -> Result<()>;
 
     #[allow(dead_code)]
     fn type_name() -> &'static str;
 }
+
+/// A value to bind to a column.
+pub enum SqlValue<'a> {
+    Text(Option<&'a str>),
+    Bool(Option<bool>),
+    Int(Option<i64>),
+    Timestamp(Option<DateTime<Utc>>),
+    TextArray(&'a [String]),
+    Json(Option<&'a Value>),
+}
+
+impl<'a> SqlValue<'a> {
+    fn bind<'q>(self, query: Query<'q, Postgres, PgArguments>) -> Query<'q, Postgres, PgArguments>
+    where
+        'a: 'q,
+    {
+        match self {
+            SqlValue::Text(value) => query.bind(value),
+            SqlValue::Bool(value) => query.bind(value),
+            SqlValue::Int(value) => query.bind(value),
+            SqlValue::Timestamp(value) => query.bind(value),
+            SqlValue::TextArray(value) => query.bind(value),
+            SqlValue::Json(value) => query.bind(value),
+        }
+    }
+}
+
+// ON CONFLICT clause of an insert of `columns`. `content` is the text column
+// kept by InsertKeepContent, `backfill` the columns it fills when empty.
+fn on_conflict(
+    columns: &[&str],
+    strategy: InsertStrategy,
+    content: &str,
+    backfill: &[&str],
+) -> String {
+    let has = |name: &str| columns.contains(&name);
+    let mut sets = Vec::new();
+    let mut condition = String::new();
+
+    match strategy {
+        InsertStrategy::InsertIgnore => {}
+        InsertStrategy::InsertUpdate => {
+            sets = columns
+                .iter()
+                .filter(|column| **column != "id")
+                .map(|column| format!("{0} = excluded.{0}", column))
+                .collect();
+        }
+        InsertStrategy::InsertKeepContent => {
+            // Deleted content never replaces earlier content, and the flags
+            // stay set once a snapshot was seen deleted.
+            let flags: Vec<&str> = DELETED_FLAGS.into_iter().filter(|f| has(f)).collect();
+            let filled: Vec<&str> = backfill.iter().copied().filter(|c| has(c)).collect();
+            if flags.is_empty() && filled.is_empty() {
+                return " ON CONFLICT DO NOTHING".to_string();
+            }
+
+            if has("author") && has("author_deleted") {
+                sets.push(
+                    "author = CASE WHEN excluded.author_deleted THEN t.author ELSE excluded.author END"
+                        .to_string(),
+                );
+            }
+            if has(content) && has("body_deleted") && has("body_removed") {
+                sets.push(format!(
+                    "{0} = CASE WHEN excluded.body_deleted OR excluded.body_removed THEN t.{0} ELSE excluded.{0} END",
+                    content
+                ));
+            }
+            for flag in &flags {
+                sets.push(format!(
+                    "{0} = COALESCE(t.{0}, false) OR excluded.{0}",
+                    flag
+                ));
+            }
+            if has("deleted_on") {
+                sets.push("deleted_on = LEAST(t.deleted_on, excluded.deleted_on)".to_string());
+            }
+
+            // Rows stored before a column was added get it from the next
+            // snapshot.
+            for column in &filled {
+                sets.push(format!("{0} = COALESCE(excluded.{0}, t.{0})", column));
+            }
+
+            let mut changes: Vec<String> = filled
+                .iter()
+                .map(|column| format!("t.{0} IS NULL AND excluded.{0} IS NOT NULL", column))
+                .collect();
+            if !flags.is_empty() {
+                let old: Vec<String> = flags.iter().map(|flag| format!("t.{}", flag)).collect();
+                let new: Vec<String> = flags
+                    .iter()
+                    .map(|flag| format!("excluded.{}", flag))
+                    .collect();
+                changes.push(format!(
+                    "({}) IS DISTINCT FROM ({})",
+                    old.join(", "),
+                    new.join(", ")
+                ));
+            }
+            condition = format!(" WHERE {}", changes.join(" OR "));
+        }
+    }
+
+    if sets.is_empty() {
+        return " ON CONFLICT DO NOTHING".to_string();
+    }
+
+    format!(
+        " ON CONFLICT (id) DO UPDATE SET {}{}",
+        sets.join(", "),
+        condition
+    )
+}
+
+/// Insert a row into the selected columns of a table. The columns that are
+/// not selected are skipped, the columns missing from `values` are NULL.
+/// `content` is the text column kept by `InsertKeepContent`, and `backfill`
+/// the columns it fills in existing rows where they are NULL.
+pub async fn insert_row<T: DBTable>(
+    pool: &PgPool,
+    strategy: InsertStrategy,
+    content: &str,
+    backfill: &[&str],
+    values: Vec<(&'static str, SqlValue<'_>)>,
+) -> Result<()> {
+    let selected = T::columns();
+    let values: Vec<(&str, SqlValue)> = values
+        .into_iter()
+        .filter(|(name, _)| selected.iter().any(|(column, _)| column == name))
+        .collect();
+
+    let columns: Vec<&str> = values.iter().map(|(name, _)| *name).collect();
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("${}", i)).collect();
+    let sql = format!(
+        "INSERT INTO {} AS t ({}) VALUES ({}){}",
+        T::table_name(),
+        columns.join(", "),
+        placeholders.join(", "),
+        on_conflict(&columns, strategy, content, backfill)
+    );
+
+    let mut query = sqlx::query(&sql);
+    for (_, value) in values {
+        query = value.bind(query);
+    }
+    query.execute(pool).await?;
+
+    Ok(())
+}
+
+/// Fill the deleted flags of the existing rows of a table, after adding the
+/// columns. `content` is the text column, `body` or `selftext`.
+pub async fn fill_deleted_flags<T: DBTable>(pool: &PgPool, content: &str) {
+    // Literals rather than parameters, the statement uses any of them
+    // depending on the columns selected.
+    let deleted = format!("'{}'", DELETED);
+    let removed = format!("'{}'", REMOVED);
+    let mut sets = Vec::new();
+    if T::has_column("author_deleted") && T::has_column("author") {
+        sets.push(format!(
+            "author_deleted = author IN ({}, {})",
+            deleted, removed
+        ));
+    }
+    if T::has_column(content) {
+        if T::has_column("body_deleted") {
+            sets.push(format!("body_deleted = {} = {}", content, deleted));
+        }
+        if T::has_column("body_removed") {
+            sets.push(format!("body_removed = {} = {}", content, removed));
+        }
+        if T::has_column("deleted_on") && T::has_column("author") && T::has_column("retrieved_on") {
+            sets.push(format!(
+                "deleted_on = CASE WHEN author IN ({1}, {2}) OR {0} IN ({1}, {2}) THEN retrieved_on END",
+                content, deleted, removed
+            ));
+        }
+    }
+    if sets.is_empty() {
+        return;
+    }
+
+    log::info!("Filling the deleted flags of {}", T::table_name());
+    sqlx::query(&format!(
+        "UPDATE {} SET {}",
+        T::table_name(),
+        sets.join(", ")
+    ))
+    .execute(pool)
+    .await
+    .expect("Failed to fill the deleted flags.");
+}
+
+/// Fill `is_edited` of the existing rows of a table, after adding the column.
+/// Rows with an edit time were edited. The others stay NULL, they were stored
+/// without an edit time whether `edited` was `false` or `true`.
+pub async fn fill_edited_flags<T: DBTable>(pool: &PgPool) {
+    if !T::has_column("edited") {
+        return;
+    }
+
+    log::info!("Filling the edited flags of {}", T::table_name());
+    sqlx::query(&format!(
+        "UPDATE {} SET is_edited = true WHERE edited > to_timestamp(0)",
+        T::table_name()
+    ))
+    .execute(pool)
+    .await
+    .expect("Failed to fill the edited flags.");
+}
+
+// Decodes base 36 ids in SQL, like `base36::decode`, for the rows stored
+// before the numeric id columns were added.
+const BASE36_FUNCTION: &str = "CREATE OR REPLACE FUNCTION reader_base36(id text) RETURNS bigint \
+    LANGUAGE plpgsql IMMUTABLE AS $$ \
+    DECLARE value bigint := 0; digit integer; c text; \
+    BEGIN \
+        id := lower(regexp_replace(id, '^t[0-9]_', '')); \
+        IF id IS NULL OR id = '' THEN RETURN NULL; END IF; \
+        FOREACH c IN ARRAY regexp_split_to_array(id, '') LOOP \
+            digit := strpos('0123456789abcdefghijklmnopqrstuvwxyz', c) - 1; \
+            IF digit < 0 THEN RETURN NULL; END IF; \
+            value := value * 36 + digit; \
+        END LOOP; \
+        RETURN value; \
+    EXCEPTION WHEN numeric_value_out_of_range THEN RETURN NULL; \
+    END $$";
+
+/// Fill the numeric id columns of the existing rows of a table. `columns`
+/// holds (numeric column, text column) pairs. `parent_kind` is filled from
+/// `parent_id` when the table has both.
+pub async fn fill_id_nums<T: DBTable>(pool: &PgPool, columns: &[(&str, &str)]) {
+    let mut sets: Vec<String> = columns
+        .iter()
+        .filter(|(num, text)| T::has_column(num) && T::has_column(text))
+        .map(|(num, text)| format!("{} = reader_base36({})", num, text))
+        .collect();
+    if T::has_column("parent_kind") && T::has_column("parent_id") {
+        sets.push("parent_kind = substring(parent_id from '^(t[0-9])_')".to_string());
+    }
+    if sets.is_empty() {
+        return;
+    }
+
+    sqlx::query(BASE36_FUNCTION)
+        .execute(pool)
+        .await
+        .expect("Failed to create the base36 function.");
+
+    log::info!("Filling the numeric ids of {}", T::table_name());
+    sqlx::query(&format!(
+        "UPDATE {} SET {}",
+        T::table_name(),
+        sets.join(", ")
+    ))
+    .execute(pool)
+    .await
+    .expect("Failed to fill the numeric ids.");
+}
+
+/// Index the numeric id columns of a table.
+pub async fn create_id_indexes<T: DBTable>(pool: &PgPool, columns: &[&str]) {
+    for column in columns.iter().filter(|column| T::has_column(column)) {
+        log::info!("Indexing {}.{}", T::table_name(), column);
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {0}_{1}_idx ON {0} ({1})",
+            T::table_name(),
+            column
+        ))
+        .execute(pool)
+        .await
+        .expect("Failed to create index.");
+    }
+}
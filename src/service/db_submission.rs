@@ -1,29 +1,77 @@
-use crate::service::db_object::DBObject;
+use crate::service::base36;
+use crate::service::db_object::{
+    create_id_indexes, fill_deleted_flags, fill_edited_flags, fill_id_nums, insert_row, DBObject,
+    SqlValue, DELETED, REMOVED,
+};
+use crate::service::error::{ErrorPolicy, Result};
+use crate::service::raw_object::AnyTimestamp;
 use crate::service::raw_submission::RedditSubmission;
 use crate::service::{DBTable, InsertStrategy};
+use serde_json::Value;
 use sqlx::types::chrono::{DateTime, Utc};
 
 use sqlx::PgPool;
 
 pub const TABLE_SUBMISSIONS: &str = "submissions";
 
+// Columns added after the first releases. Re-importing a dump fills them in
+// the rows stored before.
+const BACKFILLED_COLUMNS: [&str; 14] = [
+    "is_edited",
+    "subreddit_id",
+    "over_18",
+    "spoiler",
+    "locked",
+    "link_flair_text",
+    "author_flair_text",
+    "gilded",
+    "distinguished",
+    "num_crossposts",
+    "thumbnail",
+    "is_video",
+    "media",
+    "secure_media",
+];
+
 #[derive(Clone)]
 pub struct DBRedditSubmission {
     pub author: String,
-    pub created_utc: DateTime<Utc>,
+    pub created_utc: Option<DateTime<Utc>>,
     pub domain: String,
-    pub edited: DateTime<Utc>,
+    pub edited: Option<DateTime<Utc>>,
+    pub is_edited: Option<bool>,
     pub id: String,
     pub is_self: bool,
     pub num_comments: i32,
     pub permalink: String,
-    pub retrieved_on: DateTime<Utc>,
+    pub retrieved_on: Option<DateTime<Utc>>,
     pub score: i64,
     pub selftext: String,
     pub stickied: bool,
     pub subreddit: String,
     pub title: String,
     pub url: String,
+    pub matched_by: String,
+    pub matched_terms: Vec<String>,
+    pub author_deleted: bool,
+    pub body_deleted: bool,
+    pub body_removed: bool,
+    // Retrieval time of the first snapshot seen deleted or removed.
+    pub deleted_on: Option<DateTime<Utc>>,
+    pub subreddit_id: Option<String>,
+    pub over_18: Option<bool>,
+    pub spoiler: Option<bool>,
+    pub locked: Option<bool>,
+    pub link_flair_text: Option<String>,
+    pub author_flair_text: Option<String>,
+    pub gilded: Option<i32>,
+    pub distinguished: Option<String>,
+    pub num_crossposts: Option<i32>,
+    pub thumbnail: Option<String>,
+    pub is_video: Option<bool>,
+    pub media: Option<Value>,
+    pub secure_media: Option<Value>,
+    pub id_num: Option<i64>,
 }
 
 impl DBRedditSubmission {
@@ -55,6 +103,7 @@ impl DBTable for DBRedditSubmission {
             ("created_utc", "timestamp with time zone"),
             ("domain", "text"),
             ("edited", "timestamp with time zone"),
+            ("is_edited", "boolean"),
             ("id", "text"),
             ("is_self", "boolean"),
             ("num_comments", "integer"),
@@ -67,6 +116,25 @@ impl DBTable for DBRedditSubmission {
             ("subreddit_id", "text"),
             ("title", "text"),
             ("url", "text"),
+            ("matched_by", "text"),
+            ("matched_terms", "text[]"),
+            ("author_deleted", "boolean"),
+            ("body_deleted", "boolean"),
+            ("body_removed", "boolean"),
+            ("deleted_on", "timestamp with time zone"),
+            ("over_18", "boolean"),
+            ("spoiler", "boolean"),
+            ("locked", "boolean"),
+            ("link_flair_text", "text"),
+            ("author_flair_text", "text"),
+            ("gilded", "integer"),
+            ("distinguished", "text"),
+            ("num_crossposts", "integer"),
+            ("thumbnail", "text"),
+            ("is_video", "boolean"),
+            ("media", "jsonb"),
+            ("secure_media", "jsonb"),
+            ("id_num", "bigint"),
         ];
         types.to_vec()
     }
@@ -80,6 +148,35 @@ impl DBTable for DBRedditSubmission {
         .execute(pool)
         .await
         .expect("Failed to add primary key.");
+
+        create_id_indexes::<Self>(pool, &["id_num"]).await;
+    }
+
+    async fn post_add_columns(pool: &PgPool, added: &[&str]) {
+        if added.contains(&"author_deleted") {
+            fill_deleted_flags::<Self>(pool, "selftext").await;
+        }
+        if added.contains(&"is_edited") {
+            fill_edited_flags::<Self>(pool).await;
+        }
+        if added.contains(&"id_num") {
+            fill_id_nums::<Self>(pool, &[("id_num", "id")]).await;
+            create_id_indexes::<Self>(pool, &["id_num"]).await;
+        }
+    }
+
+    fn migrations() -> Vec<(&'static str, String)> {
+        if !Self::has_column("edited") {
+            return vec![];
+        }
+
+        vec![(
+            "submissions_edited_null",
+            format!(
+                "UPDATE {} SET edited = NULL WHERE edited = to_timestamp(0)",
+                TABLE_SUBMISSIONS
+            ),
+        )]
     }
 }
 
@@ -88,10 +185,15 @@ impl DBObject for DBRedditSubmission {
         "DBRedditSubmission"
     }
 
-    async fn insert(&self, pool: &PgPool, insert_strategy: InsertStrategy, skip_exists: bool) {
+    async fn insert(
+        &self,
+        pool: &PgPool,
+        insert_strategy: InsertStrategy,
+        skip_exists: bool,
+    ) -> Result<()> {
         // Skip exists and InsertUpdate are mutually exclusive.
-        if insert_strategy == InsertStrategy::InsertUpdate && skip_exists {
-            panic!("Cannot skip exists and use InsertUpdate.");
+        if insert_strategy != InsertStrategy::InsertIgnore && skip_exists {
+            panic!("Cannot skip exists and update existing rows.");
         }
 
         if skip_exists {
@@ -99,88 +201,135 @@ impl DBObject for DBRedditSubmission {
             let exists = sqlx::query(&sql)
                 .bind(&self.id)
                 .fetch_optional(pool)
-                .await
-                .unwrap_or_else(|err| {
-                    log::error!(
-                        "[DBRedditSubmission] Failed to check if submission exists: {}",
-                        err
-                    );
-                    panic!("Error1");
-                });
+                .await?;
 
             if exists.is_some() {
-                return;
+                return Ok(());
             }
         }
 
-        let sql;
-        if insert_strategy == InsertStrategy::InsertUpdate {
-            sql = format!(
-                "INSERT INTO {} (author, created_utc, domain, edited, id, is_self, num_comments, permalink, \
-                retrieved_on, score, selftext, stickied, subreddit, title, url) VALUES ($1, $2, $3, $4, $5, \
-                $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) ON CONFLICT (id) DO UPDATE SET \
-                author = $1, created_utc = $2, domain = $3, edited = $4, is_self = $6, num_comments = $7, \
-                permalink = $8, retrieved_on = $9, score = $10, selftext = $11, stickied = $12, subreddit = $13, \
-                title = $14, url = $15",
-
-                TABLE_SUBMISSIONS
-            );
-        } else if insert_strategy == InsertStrategy::InsertIgnore {
-            sql = format!(
-                "INSERT INTO {} (author, created_utc, domain, edited, id, is_self, num_comments, permalink, \
-                retrieved_on, score, selftext, stickied, subreddit, title, url) VALUES ($1, $2, $3, $4, $5, \
-                $6, $7, $8, $9, $10, $11, $12, $13, $14, $15) ON CONFLICT DO NOTHING",
-
-                TABLE_SUBMISSIONS
-            );
-        } else {
-            panic!("Invalid insert strategy.");
-        }
-
-        sqlx::query(&sql)
-            .bind(&self.author)
-            .bind(self.created_utc)
-            .bind(&self.domain)
-            .bind(self.edited)
-            .bind(&self.id)
-            .bind(&self.is_self)
-            .bind(&self.num_comments)
-            .bind(&self.permalink)
-            .bind(self.retrieved_on)
-            .bind(&self.score)
-            .bind(&self.selftext)
-            .bind(&self.stickied)
-            .bind(&self.subreddit)
-            .bind(&self.title)
-            .bind(&self.url)
-            .execute(pool)
-            .await
-            .unwrap_or_else(|err| {
-                log::error!("[DBRedditSubmission] Failed to insert submission: {}", err);
-                panic!("Error2");
-            });
+        insert_row::<Self>(
+            pool,
+            insert_strategy,
+            "selftext",
+            &BACKFILLED_COLUMNS,
+            self.values(),
+        )
+        .await
     }
 }
 
-impl From<&RedditSubmission> for DBRedditSubmission {
-    fn from(submission: &RedditSubmission) -> Self {
-        DBRedditSubmission {
+impl DBRedditSubmission {
+    /// Values of the columns, in the order of `sql_types`.
+    pub fn values(&self) -> Vec<(&'static str, SqlValue<'_>)> {
+        vec![
+            ("author", SqlValue::Text(Some(&self.author))),
+            ("created_utc", SqlValue::Timestamp(self.created_utc)),
+            ("domain", SqlValue::Text(Some(&self.domain))),
+            ("edited", SqlValue::Timestamp(self.edited)),
+            ("is_edited", SqlValue::Bool(self.is_edited)),
+            ("id", SqlValue::Text(Some(&self.id))),
+            ("is_self", SqlValue::Bool(Some(self.is_self))),
+            (
+                "num_comments",
+                SqlValue::Int(Some(self.num_comments.into())),
+            ),
+            ("permalink", SqlValue::Text(Some(&self.permalink))),
+            ("retrieved_on", SqlValue::Timestamp(self.retrieved_on)),
+            ("score", SqlValue::Int(Some(self.score))),
+            ("selftext", SqlValue::Text(Some(&self.selftext))),
+            ("stickied", SqlValue::Bool(Some(self.stickied))),
+            ("subreddit", SqlValue::Text(Some(&self.subreddit))),
+            ("subreddit_id", SqlValue::Text(self.subreddit_id.as_deref())),
+            ("title", SqlValue::Text(Some(&self.title))),
+            ("url", SqlValue::Text(Some(&self.url))),
+            ("matched_by", SqlValue::Text(Some(&self.matched_by))),
+            ("matched_terms", SqlValue::TextArray(&self.matched_terms)),
+            ("author_deleted", SqlValue::Bool(Some(self.author_deleted))),
+            ("body_deleted", SqlValue::Bool(Some(self.body_deleted))),
+            ("body_removed", SqlValue::Bool(Some(self.body_removed))),
+            ("deleted_on", SqlValue::Timestamp(self.deleted_on)),
+            ("over_18", SqlValue::Bool(self.over_18)),
+            ("spoiler", SqlValue::Bool(self.spoiler)),
+            ("locked", SqlValue::Bool(self.locked)),
+            (
+                "link_flair_text",
+                SqlValue::Text(self.link_flair_text.as_deref()),
+            ),
+            (
+                "author_flair_text",
+                SqlValue::Text(self.author_flair_text.as_deref()),
+            ),
+            ("gilded", SqlValue::Int(self.gilded.map(i64::from))),
+            (
+                "distinguished",
+                SqlValue::Text(self.distinguished.as_deref()),
+            ),
+            (
+                "num_crossposts",
+                SqlValue::Int(self.num_crossposts.map(i64::from)),
+            ),
+            ("thumbnail", SqlValue::Text(self.thumbnail.as_deref())),
+            ("is_video", SqlValue::Bool(self.is_video)),
+            ("media", SqlValue::Json(self.media.as_ref())),
+            ("secure_media", SqlValue::Json(self.secure_media.as_ref())),
+            ("id_num", SqlValue::Int(self.id_num)),
+        ]
+    }
+
+    /// Convert from RedditSubmission, applying `policy` to the values that
+    /// cannot be converted.
+    pub fn new(submission: &RedditSubmission, policy: ErrorPolicy) -> Result<Self> {
+        let author_deleted = submission.author == DELETED || submission.author == REMOVED;
+        let body_deleted = submission.selftext == DELETED;
+        let body_removed = submission.selftext == REMOVED;
+        let retrieved_on = AnyTimestamp::to_optional_datetime(submission.retrieved(), policy)?;
+
+        Ok(DBRedditSubmission {
             author: submission.author.clone(),
-            created_utc: DateTime::<Utc>::from(&submission.created_utc),
+            created_utc: submission.created_utc.to_datetime(policy)?,
             domain: submission.domain.clone().unwrap_or_default(),
-            edited: DateTime::<Utc>::from(&submission.edited),
+            edited: match &submission.edited {
+                Some(edited) => edited.to_edited_datetime(policy)?,
+                None => None,
+            },
+            is_edited: submission.edited.as_ref().map(AnyTimestamp::is_edited),
             id: submission.id.clone(),
-            is_self: submission.is_self,
+            is_self: submission.is_self.unwrap_or_default(),
             num_comments: submission.num_comments,
-            permalink: submission.permalink.clone(),
-            retrieved_on: DateTime::<Utc>::from(&submission.retrieved_on),
+            permalink: submission.permalink.clone().unwrap_or_default(),
+            retrieved_on,
             score: submission.score,
             selftext: submission.selftext.clone(),
             stickied: submission.stickied.unwrap_or_default(),
             subreddit: submission.subreddit.clone(),
             title: submission.title.clone(),
             url: submission.url.clone(),
-        }
+            matched_by: String::new(),
+            matched_terms: vec![],
+            author_deleted,
+            body_deleted,
+            body_removed,
+            deleted_on: if author_deleted || body_deleted || body_removed {
+                retrieved_on
+            } else {
+                None
+            },
+            subreddit_id: submission.subreddit_id.clone(),
+            over_18: submission.over_18,
+            spoiler: submission.spoiler,
+            locked: submission.locked,
+            link_flair_text: submission.link_flair_text.clone(),
+            author_flair_text: submission.author_flair_text.clone(),
+            gilded: submission.gilded,
+            distinguished: submission.distinguished.clone(),
+            num_crossposts: submission.num_crossposts,
+            thumbnail: submission.thumbnail.clone(),
+            is_video: submission.is_video,
+            media: submission.media.clone(),
+            secure_media: submission.secure_media.clone(),
+            id_num: base36::decode(&submission.id),
+        })
     }
 }
 
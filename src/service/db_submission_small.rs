@@ -1,4 +1,5 @@
-use super::db_object::DBObject;
+use super::db_object::{insert_row, DBObject, SqlValue};
+use super::error::{Error, Result};
 use super::raw_submission::RedditSubmission;
 use super::DBTable;
 use super::InsertStrategy;
@@ -28,6 +29,12 @@ impl DBTable for DBRedditSubmissionSmall {
     }
 
     async fn post_create_table(_pool: &PgPool) {}
+
+    async fn post_add_columns(_pool: &PgPool, _added: &[&str]) {}
+
+    fn migrations() -> Vec<(&'static str, String)> {
+        vec![]
+    }
 }
 
 impl DBObject for DBRedditSubmissionSmall {
@@ -35,36 +42,32 @@ impl DBObject for DBRedditSubmissionSmall {
         "DBSubmissionSmall"
     }
 
-    async fn insert(&self, pool: &PgPool, _strategy: InsertStrategy, _ignore: bool) {
-        let sql = format!(
-            "INSERT INTO {} (author, created_utc, id, subreddit) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
-            Self::table_name()
-        );
+    async fn insert(&self, pool: &PgPool, _strategy: InsertStrategy, _ignore: bool) -> Result<()> {
+        insert_row::<Self>(pool, InsertStrategy::InsertIgnore, "", &[], self.values()).await
+    }
+}
 
-        sqlx::query(&sql)
-            .bind(&self.author)
-            .bind(self.created_utc)
-            .bind(&self.id)
-            .bind(&self.subreddit)
-            .execute(pool)
-            .await
-            .unwrap_or_else(|err| {
-                log::error!(
-                    "[DBRedditSubmissionSmall] Failed to insert submission: {}",
-                    err
-                );
-                panic!("Error2");
-            });
+impl DBRedditSubmissionSmall {
+    /// Values of the columns, in the order of `sql_types`.
+    pub fn values(&self) -> Vec<(&'static str, SqlValue<'_>)> {
+        vec![
+            ("author", SqlValue::Text(Some(&self.author))),
+            ("created_utc", SqlValue::Timestamp(Some(self.created_utc))),
+            ("id", SqlValue::Text(Some(&self.id))),
+            ("subreddit", SqlValue::Text(Some(&self.subreddit))),
+        ]
     }
 }
 
-impl From<&RedditSubmission> for DBRedditSubmissionSmall {
-    fn from(submission: &RedditSubmission) -> Self {
-        DBRedditSubmissionSmall {
+impl TryFrom<&RedditSubmission> for DBRedditSubmissionSmall {
+    type Error = Error;
+
+    fn try_from(submission: &RedditSubmission) -> Result<Self> {
+        Ok(DBRedditSubmissionSmall {
             author: submission.author.clone(),
-            created_utc: DateTime::<Utc>::from(&submission.created_utc),
+            created_utc: DateTime::<Utc>::try_from(&submission.created_utc)?,
             id: submission.id.clone(),
             subreddit: submission.subreddit.clone(),
-        }
+        })
     }
 }
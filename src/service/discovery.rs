@@ -0,0 +1,68 @@
+use crate::service::cfg::Discovery;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::path::Path;
+use walkdir::WalkDir;
+
+// Compile a list of glob patterns into a single matcher.
+fn build_globset(patterns: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .unwrap_or_else(|err| panic!("Invalid glob pattern {}: {}", pattern, err));
+        builder.add(glob);
+    }
+
+    builder
+        .build()
+        .unwrap_or_else(|err| panic!("Failed to build glob set: {}", err))
+}
+
+/// Recursively list the dump files under `target_folder`.
+///
+/// Paths are matched relative to `target_folder` against the include and
+/// exclude patterns. The returned list is sorted and is the order in which
+/// the files are ingested.
+pub fn discover_files(target_folder: &str, discovery: &Discovery) -> Vec<String> {
+    let include = build_globset(&discovery.include);
+    let exclude = build_globset(&discovery.exclude);
+    let root = Path::new(target_folder);
+
+    let mut files = Vec::new();
+    let walker = WalkDir::new(root)
+        .follow_links(discovery.follow_symlinks)
+        .max_depth(discovery.max_depth);
+
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                // Unreadable directories and symlink loops end up here.
+                log::error!("Failed to read directory entry: {}", err);
+                continue;
+            }
+        };
+
+        if entry.path_is_symlink() && !discovery.follow_symlinks {
+            log::info!("Skipping symlink: {}", entry.path().display());
+            continue;
+        }
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if !include.is_match(relative) || exclude.is_match(relative) {
+            log::debug!("Skipping file: {}", entry.path().display());
+            continue;
+        }
+
+        match entry.path().to_str() {
+            Some(path) => files.push(path.to_string()),
+            None => log::error!("Skipping non UTF-8 path: {}", entry.path().display()),
+        }
+    }
+
+    files.sort();
+    files
+}
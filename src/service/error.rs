@@ -0,0 +1,178 @@
+use serde_json::error::Category;
+use std::fmt;
+
+/// Errors returned while reading, converting and storing records.
+#[derive(Debug)]
+pub enum Error {
+    /// A line is not valid JSON, or a value could not be converted, e.g. an
+    /// out of range timestamp.
+    Parse(String),
+    /// A line does not have the expected fields or value types.
+    Schema(String),
+    /// The database rejected a query.
+    Db(sqlx::Error),
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(msg) => write!(f, "parse error: {}", msg),
+            Error::Schema(msg) => write!(f, "schema error: {}", msg),
+            Error::Db(err) => write!(f, "database error: {}", err),
+            Error::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Tells if the connection to the database failed, rather than a query on
+    /// a record.
+    pub fn is_connection(&self) -> bool {
+        matches!(
+            self,
+            Error::Db(
+                sqlx::Error::Io(_)
+                    | sqlx::Error::PoolTimedOut
+                    | sqlx::Error::PoolClosed
+                    | sqlx::Error::Tls(_)
+                    | sqlx::Error::Protocol(_)
+            )
+        )
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        match err.classify() {
+            // Invalid or truncated JSON.
+            Category::Syntax | Category::Eof | Category::Io => Error::Parse(err.to_string()),
+            // Missing fields, or values of an unexpected type.
+            Category::Data => Error::Schema(err.to_string()),
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Error::Db(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// What to do with a record that cannot be converted or inserted.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorPolicy {
+    /// Skip the record and write it to the rejected lines file.
+    #[default]
+    Reject,
+    /// Store NULL for the values that cannot be converted. Records that
+    /// cannot be parsed at all are rejected.
+    Null,
+    /// Stop the ingestion.
+    Abort,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::raw_comment::RedditComment;
+    use crate::service::raw_object::AnyTimestamp;
+    use crate::service::DBRedditComment;
+    use serde_json::{json, Value};
+    use sqlx::types::chrono::{DateTime, Utc};
+
+    fn comment(fields: Value) -> String {
+        let mut line = json!({
+            "author": "a",
+            "body": "b",
+            "created_utc": 1136500000,
+            "edited": false,
+            "gilded": 0,
+            "id": "c1",
+            "link_id": "t3_abc",
+            "parent_id": "t3_abc",
+            "retrieved_on": 1600000100,
+            "score": 1,
+            "subreddit": "test",
+            "subreddit_id": "t5_1",
+        });
+        for (key, value) in fields.as_object().unwrap() {
+            line[key] = value.clone();
+        }
+        line.to_string()
+    }
+
+    #[test]
+    fn wrong_json_type_is_schema() {
+        let line = comment(json!({"score": "high"}));
+        assert!(matches!(
+            RedditComment::try_from(line.as_str()),
+            Err(Error::Schema(_))
+        ));
+        let line = comment(json!({"parent_id": [1]}));
+        assert!(matches!(
+            RedditComment::try_from(line.as_str()),
+            Err(Error::Schema(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_json_is_parse() {
+        let line = comment(json!({}));
+        for invalid in [&line[..line.len() - 1], "{\"id\": c1}", "not json"] {
+            assert!(
+                matches!(RedditComment::try_from(invalid), Err(Error::Parse(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range_timestamp_is_parse() {
+        for timestamp in [
+            AnyTimestamp::Integer(i64::MAX),
+            AnyTimestamp::Float(1e300),
+            AnyTimestamp::String("-9999999999999999999999".to_string()),
+        ] {
+            assert!(
+                matches!(DateTime::<Utc>::try_from(&timestamp), Err(Error::Parse(_))),
+                "{:?}",
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn null_policy_stores_none() {
+        let line = comment(json!({"created_utc": "yesterday", "parent_id": -1}));
+        let parsed = RedditComment::try_from(line.as_str()).unwrap();
+
+        assert!(matches!(
+            DBRedditComment::new(&parsed, ErrorPolicy::Reject),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            DBRedditComment::new(&parsed, ErrorPolicy::Abort),
+            Err(Error::Parse(_))
+        ));
+
+        let stored = DBRedditComment::new(&parsed, ErrorPolicy::Null).unwrap();
+        assert_eq!(stored.created_utc, None);
+        assert_eq!(stored.parent_id, "");
+        assert_eq!(stored.parent_id_num, None);
+        assert!(stored.retrieved_on.is_some());
+    }
+}
@@ -0,0 +1,888 @@
+use crate::service::prefilter::{may_equal, may_match_author, may_match_subreddit};
+use crate::service::raw_comment::{ParentId, RedditComment};
+use crate::service::raw_submission::RedditSubmission;
+use crate::service::RecordFilter;
+use chrono::{DateTime, NaiveDate};
+use regex::{Regex, RegexBuilder};
+
+/// A record field that filter expressions can refer to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Field {
+    Id,
+    Author,
+    Subreddit,
+    SubredditId,
+    Title,
+    Selftext,
+    Body,
+    Text,
+    Domain,
+    Url,
+    LinkId,
+    ParentId,
+    Distinguished,
+    LinkFlairText,
+    Score,
+    NumComments,
+    Gilded,
+    Controversiality,
+    CreatedUtc,
+    Over18,
+    IsSelf,
+    Stickied,
+    IsSubmitter,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FieldType {
+    Str,
+    Int,
+    Time,
+    Bool,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        let field = match name {
+            "id" => Field::Id,
+            "author" => Field::Author,
+            "subreddit" => Field::Subreddit,
+            "subreddit_id" => Field::SubredditId,
+            "title" => Field::Title,
+            "selftext" => Field::Selftext,
+            "body" => Field::Body,
+            "text" => Field::Text,
+            "domain" => Field::Domain,
+            "url" => Field::Url,
+            "link_id" => Field::LinkId,
+            "parent_id" => Field::ParentId,
+            "distinguished" => Field::Distinguished,
+            "link_flair_text" => Field::LinkFlairText,
+            "score" => Field::Score,
+            "num_comments" => Field::NumComments,
+            "gilded" => Field::Gilded,
+            "controversiality" => Field::Controversiality,
+            "created_utc" => Field::CreatedUtc,
+            "over_18" => Field::Over18,
+            "is_self" => Field::IsSelf,
+            "stickied" => Field::Stickied,
+            "is_submitter" => Field::IsSubmitter,
+            _ => return None,
+        };
+
+        Some(field)
+    }
+
+    fn field_type(self) -> FieldType {
+        match self {
+            Field::Score | Field::NumComments | Field::Gilded | Field::Controversiality => {
+                FieldType::Int
+            }
+            Field::CreatedUtc => FieldType::Time,
+            Field::Over18 | Field::IsSelf | Field::Stickied | Field::IsSubmitter => FieldType::Bool,
+            _ => FieldType::Str,
+        }
+    }
+
+    // JSON key of the field, for the checks on the raw line.
+    fn json_key(self) -> Option<&'static [u8]> {
+        match self {
+            Field::Author => Some(b"\"author\""),
+            Field::Subreddit => Some(b"\"subreddit\""),
+            Field::SubredditId => Some(b"\"subreddit_id\""),
+            _ => None,
+        }
+    }
+}
+
+/// The value of a record field. Fields a record does not have are `Null`.
+pub enum FieldValue<'a> {
+    Str(&'a str),
+    Int(i64),
+    Bool(bool),
+    Null,
+}
+
+/// A parsed record that filters can be evaluated against.
+pub trait FilterRecord {
+    fn subreddit(&self) -> &str;
+    fn subreddit_id(&self) -> Option<&str>;
+    fn author(&self) -> &str;
+    /// Title and self text of submissions, body of comments.
+    fn texts(&self) -> Vec<&str>;
+    fn field(&self, field: Field) -> FieldValue<'_>;
+}
+
+fn opt_str(value: &Option<String>) -> FieldValue<'_> {
+    value.as_deref().map_or(FieldValue::Null, FieldValue::Str)
+}
+
+fn opt_bool(value: Option<bool>) -> FieldValue<'static> {
+    value.map_or(FieldValue::Null, FieldValue::Bool)
+}
+
+impl FilterRecord for RedditSubmission {
+    fn subreddit(&self) -> &str {
+        &self.subreddit
+    }
+
+    fn subreddit_id(&self) -> Option<&str> {
+        self.subreddit_id.as_deref()
+    }
+
+    fn author(&self) -> &str {
+        &self.author
+    }
+
+    fn texts(&self) -> Vec<&str> {
+        vec![&self.title, &self.selftext]
+    }
+
+    fn field(&self, field: Field) -> FieldValue<'_> {
+        match field {
+            Field::Id => FieldValue::Str(&self.id),
+            Field::Author => FieldValue::Str(&self.author),
+            Field::Subreddit => FieldValue::Str(&self.subreddit),
+            Field::SubredditId => opt_str(&self.subreddit_id),
+            Field::Title => FieldValue::Str(&self.title),
+            Field::Selftext => FieldValue::Str(&self.selftext),
+            Field::Domain => opt_str(&self.domain),
+            Field::Url => FieldValue::Str(&self.url),
+            Field::Distinguished => opt_str(&self.distinguished),
+            Field::LinkFlairText => opt_str(&self.link_flair_text),
+            Field::Score => FieldValue::Int(self.score),
+            Field::NumComments => FieldValue::Int(self.num_comments as i64),
+            Field::Gilded => self
+                .gilded
+                .map_or(FieldValue::Null, |g| FieldValue::Int(g as i64)),
+            Field::CreatedUtc => {
+                i64::try_from(&self.created_utc).map_or(FieldValue::Null, FieldValue::Int)
+            }
+            Field::Over18 => opt_bool(self.over_18),
+            Field::IsSelf => opt_bool(self.is_self),
+            Field::Stickied => opt_bool(self.stickied),
+            _ => FieldValue::Null,
+        }
+    }
+}
+
+impl FilterRecord for RedditComment {
+    fn subreddit(&self) -> &str {
+        &self.subreddit
+    }
+
+    fn subreddit_id(&self) -> Option<&str> {
+        Some(&self.subreddit_id)
+    }
+
+    fn author(&self) -> &str {
+        &self.author
+    }
+
+    fn texts(&self) -> Vec<&str> {
+        vec![&self.body]
+    }
+
+    fn field(&self, field: Field) -> FieldValue<'_> {
+        match field {
+            Field::Id => FieldValue::Str(&self.id),
+            Field::Author => FieldValue::Str(&self.author),
+            Field::Subreddit => FieldValue::Str(&self.subreddit),
+            Field::SubredditId => FieldValue::Str(&self.subreddit_id),
+            Field::Body => FieldValue::Str(&self.body),
+            Field::LinkId => FieldValue::Str(&self.link_id),
+            Field::ParentId => match &self.parent_id {
+                Some(parent_id) => match parent_id {
+                    ParentId::String(s) => FieldValue::Str(s),
+                    ParentId::Int(_) => FieldValue::Null,
+                },
+                None => FieldValue::Null,
+            },
+            Field::Distinguished => opt_str(&self.distinguished),
+            Field::Score => FieldValue::Int(self.score),
+            Field::Gilded => FieldValue::Int(self.gilded),
+            Field::Controversiality => self
+                .controversiality
+                .map_or(FieldValue::Null, FieldValue::Int),
+            Field::CreatedUtc => {
+                i64::try_from(&self.created_utc).map_or(FieldValue::Null, FieldValue::Int)
+            }
+            Field::Stickied => opt_bool(self.stickied),
+            Field::IsSubmitter => opt_bool(self.is_submitter),
+            _ => FieldValue::Null,
+        }
+    }
+}
+
+/// The filters configured outside of the expression, usable as atoms.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NamedFilter {
+    SubredditList,
+    AuthorList,
+    ContentFilter,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+enum Literal {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+}
+
+/// A parsed and type checked filter expression.
+#[derive(Debug)]
+pub enum Expr {
+    Const(bool),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Named(NamedFilter),
+    /// String comparisons ignore case, the literal is stored lowercase.
+    CmpStr(Field, CmpOp, String),
+    CmpInt(Field, CmpOp, i64),
+    CmpBool(Field, CmpOp, bool),
+    In(Field, Vec<String>),
+    Matches(Field, Regex),
+}
+
+/// Three-valued outcome of checking an expression on a raw line.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Maybe {
+    No,
+    Unknown,
+    Yes,
+}
+
+fn compare<T: PartialOrd>(left: T, op: CmpOp, right: T) -> bool {
+    match op {
+        CmpOp::Eq => left == right,
+        CmpOp::Ne => left != right,
+        CmpOp::Lt => left < right,
+        CmpOp::Le => left <= right,
+        CmpOp::Gt => left > right,
+        CmpOp::Ge => left >= right,
+    }
+}
+
+// Evaluate a string test on a field. `text` is true if any of the texts is.
+fn eval_str(record: &dyn FilterRecord, field: Field, test: impl Fn(&str) -> bool) -> bool {
+    if field == Field::Text {
+        return record.texts().into_iter().any(test);
+    }
+
+    match record.field(field) {
+        FieldValue::Str(value) => test(value),
+        _ => false,
+    }
+}
+
+impl Expr {
+    /// Build the expression combining the configured filters, used when no
+    /// `filter` expression is given.
+    pub fn from_named(named: Vec<NamedFilter>, all: bool) -> Expr {
+        let atoms: Vec<Expr> = named.into_iter().map(Expr::Named).collect();
+
+        if atoms.is_empty() {
+            Expr::Const(false)
+        } else if all {
+            Expr::And(atoms)
+        } else {
+            Expr::Or(atoms)
+        }
+    }
+
+    /// Evaluate the expression on a parsed record.
+    ///
+    /// `named` evaluates the configured filters. Comparisons on fields the
+    /// record does not have are false.
+    pub fn eval(
+        &self,
+        record: &dyn FilterRecord,
+        named: &mut dyn FnMut(NamedFilter) -> bool,
+    ) -> bool {
+        match self {
+            Expr::Const(value) => *value,
+            Expr::And(exprs) => exprs.iter().all(|expr| expr.eval(record, named)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| expr.eval(record, named)),
+            Expr::Not(expr) => !expr.eval(record, named),
+            Expr::Named(filter) => named(*filter),
+            Expr::CmpStr(field, op, literal) => eval_str(record, *field, |value| {
+                compare(value.to_lowercase().as_str(), *op, literal)
+            }),
+            Expr::CmpInt(field, op, literal) => match record.field(*field) {
+                FieldValue::Int(value) => compare(value, *op, *literal),
+                _ => false,
+            },
+            Expr::CmpBool(field, op, literal) => match record.field(*field) {
+                FieldValue::Bool(value) => compare(value, *op, *literal),
+                _ => false,
+            },
+            Expr::In(field, literals) => eval_str(record, *field, |value| {
+                literals.contains(&value.to_lowercase())
+            }),
+            Expr::Matches(field, regex) => eval_str(record, *field, |value| regex.is_match(value)),
+        }
+    }
+
+    /// Check the expression on a raw JSON line, before deserializing it.
+    ///
+    /// Only the configured filters and equality tests on `subreddit`,
+    /// `subreddit_id` and `author` can be checked, everything else is
+    /// `Unknown`. A `No` means the record can be skipped.
+    pub fn check_raw(&self, line: &str, filter: &RecordFilter) -> Maybe {
+        match self {
+            Expr::Const(true) => Maybe::Yes,
+            Expr::Const(false) => Maybe::No,
+            Expr::And(exprs) => {
+                let mut result = Maybe::Yes;
+                for expr in exprs {
+                    match expr.check_raw(line, filter) {
+                        Maybe::No => return Maybe::No,
+                        Maybe::Unknown => result = Maybe::Unknown,
+                        Maybe::Yes => {}
+                    }
+                }
+                result
+            }
+            Expr::Or(exprs) => {
+                let mut result = Maybe::No;
+                for expr in exprs {
+                    match expr.check_raw(line, filter) {
+                        Maybe::Yes => return Maybe::Yes,
+                        Maybe::Unknown => result = Maybe::Unknown,
+                        Maybe::No => {}
+                    }
+                }
+                result
+            }
+            Expr::Not(expr) => match expr.check_raw(line, filter) {
+                Maybe::Yes => Maybe::No,
+                Maybe::No => Maybe::Yes,
+                Maybe::Unknown => Maybe::Unknown,
+            },
+            Expr::Named(named) => {
+                let may_match = match named {
+                    NamedFilter::SubredditList => may_match_subreddit(line, &filter.subreddits),
+                    NamedFilter::AuthorList => may_match_author(line, filter),
+                    NamedFilter::ContentFilter => filter.content.may_match_raw(line),
+                };
+                if may_match {
+                    Maybe::Unknown
+                } else {
+                    Maybe::No
+                }
+            }
+            Expr::CmpStr(field, CmpOp::Eq, literal) => {
+                check_raw_equal(line, *field, std::slice::from_ref(literal))
+            }
+            Expr::In(field, literals) => check_raw_equal(line, *field, literals),
+            _ => Maybe::Unknown,
+        }
+    }
+}
+
+fn check_raw_equal(line: &str, field: Field, literals: &[String]) -> Maybe {
+    match field.json_key() {
+        Some(key) if !may_equal(line, key, literals) => Maybe::No,
+        _ => Maybe::Unknown,
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Op(String),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' || c == '[' || c == ']' || c == ',' {
+            chars.next();
+            tokens.push(match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                '[' => Token::LBracket,
+                ']' => Token::RBracket,
+                _ => Token::Comma,
+            });
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        // Only quotes and backslashes are escaped, so that
+                        // regexes like `\d` can be written as is.
+                        Some((_, escaped)) if escaped == c || escaped == '\\' => {
+                            value.push(escaped)
+                        }
+                        Some((_, other)) => {
+                            value.push('\\');
+                            value.push(other);
+                        }
+                        None => return Err(format!("unterminated string at {}", pos)),
+                    },
+                    Some((_, end)) if end == c => break,
+                    Some((_, other)) => value.push(other),
+                    None => return Err(format!("unterminated string at {}", pos)),
+                }
+            }
+            tokens.push(Token::Str(value));
+        } else if c.is_ascii_digit() || c == '-' {
+            let mut number = String::new();
+            while let Some(&(_, d)) = chars.peek() {
+                if d.is_ascii_digit() || (number.is_empty() && d == '-') {
+                    number.push(d);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let value = number
+                .parse::<i64>()
+                .map_err(|_| format!("invalid number {} at {}", number, pos))?;
+            tokens.push(Token::Int(value));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&(_, d)) = chars.peek() {
+                if d.is_alphanumeric() || d == '_' {
+                    ident.push(d);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Ident(ident));
+        } else {
+            let mut op = String::new();
+            while let Some(&(_, d)) = chars.peek() {
+                if "=!<>~".contains(d) {
+                    op.push(d);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            if op.is_empty() {
+                return Err(format!("unexpected character {:?} at {}", c, pos));
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// Recursive descent parser:
+//   expr    := and ("or" and)*
+//   and     := unary ("and" unary)*
+//   unary   := "not" unary | primary
+//   primary := "(" expr ")" | "true" | "false" | filter name
+//            | field | field op literal | field "in" "[" literal, ... "]"
+struct ExprParser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    named: &'a [NamedFilter],
+}
+
+fn is_keyword(token: Option<&Token>, keyword: &str) -> bool {
+    matches!(token, Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case(keyword))
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            other => Err(format!("expected {:?}, found {:?}", expected, other)),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut exprs = vec![self.parse_and()?];
+        while is_keyword(self.peek(), "or") {
+            self.next();
+            exprs.push(self.parse_and()?);
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            Expr::Or(exprs)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut exprs = vec![self.parse_unary()?];
+        while is_keyword(self.peek(), "and") {
+            self.next();
+            exprs.push(self.parse_unary()?);
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            Expr::And(exprs)
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if is_keyword(self.peek(), "not") {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+
+        self.parse_primary()
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, String> {
+        match self.next() {
+            Some(Token::Str(value)) => Ok(Literal::Str(value)),
+            Some(Token::Int(value)) => Ok(Literal::Int(value)),
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case("true") => {
+                Ok(Literal::Bool(true))
+            }
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case("false") => {
+                Ok(Literal::Bool(false))
+            }
+            other => Err(format!("expected a literal, found {:?}", other)),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let name = match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                return Ok(expr);
+            }
+            Some(Token::Ident(name)) => name,
+            other => return Err(format!("expected a field or filter, found {:?}", other)),
+        };
+
+        // Keywords are case insensitive, like `and`, `or` and `not`.
+        if name.eq_ignore_ascii_case("true") {
+            return Ok(Expr::Const(true));
+        }
+        if name.eq_ignore_ascii_case("false") {
+            return Ok(Expr::Const(false));
+        }
+
+        let named = match name.as_str() {
+            "subreddit_list" => Some(NamedFilter::SubredditList),
+            "author_list" => Some(NamedFilter::AuthorList),
+            "content_filter" => Some(NamedFilter::ContentFilter),
+            _ => None,
+        };
+        if let Some(named) = named {
+            if !self.named.contains(&named) {
+                return Err(format!("{} is used but not configured", name));
+            }
+            return Ok(Expr::Named(named));
+        }
+
+        let field = Field::parse(&name).ok_or_else(|| format!("unknown field {}", name))?;
+        let field_type = field.field_type();
+
+        if is_keyword(self.peek(), "in") {
+            self.next();
+            if field_type != FieldType::Str {
+                return Err(format!("{} is not a text field, cannot use in", name));
+            }
+
+            self.expect(Token::LBracket)?;
+            let mut values = Vec::new();
+            loop {
+                match self.parse_literal()? {
+                    Literal::Str(value) => values.push(value.to_lowercase()),
+                    other => return Err(format!("{} compared with {:?}", name, other)),
+                }
+                match self.next() {
+                    Some(Token::Comma) => continue,
+                    Some(Token::RBracket) => break,
+                    other => return Err(format!("expected , or ], found {:?}", other)),
+                }
+            }
+            return Ok(Expr::In(field, values));
+        }
+
+        let op = match self.peek() {
+            Some(Token::Op(op)) => op.clone(),
+            Some(Token::Ident(ident)) if ident.eq_ignore_ascii_case("matches") => "~".to_string(),
+            // A bare boolean field, like `over_18`.
+            _ if field_type == FieldType::Bool => return Ok(Expr::CmpBool(field, CmpOp::Eq, true)),
+            other => {
+                return Err(format!(
+                    "expected an operator after {}, found {:?}",
+                    name, other
+                ))
+            }
+        };
+        self.next();
+
+        let literal = self.parse_literal()?;
+        if op == "~" {
+            return match (field_type, literal) {
+                (FieldType::Str, Literal::Str(pattern)) => RegexBuilder::new(&pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map(|regex| Expr::Matches(field, regex))
+                    .map_err(|err| format!("invalid regex {}: {}", pattern, err)),
+                _ => Err(format!("{} cannot be matched with a regex", name)),
+            };
+        }
+
+        let op = match op.as_str() {
+            "==" | "=" => CmpOp::Eq,
+            "!=" => CmpOp::Ne,
+            "<" => CmpOp::Lt,
+            "<=" => CmpOp::Le,
+            ">" => CmpOp::Gt,
+            ">=" => CmpOp::Ge,
+            _ => return Err(format!("unknown operator {}", op)),
+        };
+
+        match (field_type, literal) {
+            (FieldType::Str, Literal::Str(value)) => {
+                Ok(Expr::CmpStr(field, op, value.to_lowercase()))
+            }
+            (FieldType::Int, Literal::Int(value)) => Ok(Expr::CmpInt(field, op, value)),
+            (FieldType::Time, Literal::Int(value)) => Ok(Expr::CmpInt(field, op, value)),
+            (FieldType::Time, Literal::Str(value)) => {
+                Ok(Expr::CmpInt(field, op, parse_time(&value)?))
+            }
+            (FieldType::Bool, Literal::Bool(value)) if matches!(op, CmpOp::Eq | CmpOp::Ne) => {
+                Ok(Expr::CmpBool(field, op, value))
+            }
+            (_, literal) => Err(format!("{} cannot be compared with {:?}", name, literal)),
+        }
+    }
+}
+
+// Parse a date literal: `2020-01-31` or an RFC 3339 timestamp.
+fn parse_time(value: &str) -> Result<i64, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp());
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp())
+        .map_err(|_| format!("invalid date {}", value))
+}
+
+/// Parse and type check a filter expression.
+///
+/// `named` lists the filters configured outside of the expression, the only
+/// ones it may refer to.
+pub fn parse_expr(input: &str, named: &[NamedFilter]) -> Result<Expr, String> {
+    let mut parser = ExprParser {
+        tokens: tokenize(input)?,
+        pos: 0,
+        named,
+    };
+
+    let expr = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("unexpected {:?} after the expression", token));
+    }
+
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::cfg::ContentFilterConfig;
+    use crate::service::{ContentFilter, FilterCombine, SubredditFilter};
+
+    fn comment(subreddit: &str, author: &str, body: &str, score: i64) -> String {
+        serde_json::json!({
+            "id": "c1",
+            "author": author,
+            "body": body,
+            "created_utc": 1600000000,
+            "edited": false,
+            "gilded": 0,
+            "link_id": "t3_abc",
+            "parent_id": "t3_abc",
+            "retrieved_on": 1600000100,
+            "score": score,
+            "subreddit": subreddit,
+            "subreddit_id": "t5_2qm4e",
+        })
+        .to_string()
+    }
+
+    fn eval(input: &str) -> bool {
+        let record =
+            RedditComment::try_from(comment("rust", "alice", "hello", 5).as_str()).unwrap();
+        parse_expr(input, &[])
+            .unwrap()
+            .eval(&record, &mut |_| false)
+    }
+
+    fn filter(expression: &str) -> RecordFilter {
+        let content = ContentFilterConfig {
+            keywords: vec!["ferris".to_string()],
+            ..Default::default()
+        };
+        RecordFilter::new(
+            SubredditFilter::new(&["askscience".to_string()], &[]),
+            &["alice".to_string()],
+            ContentFilter::new(&content),
+            FilterCombine::Any,
+            Some(expression),
+        )
+    }
+
+    #[test]
+    fn precedence() {
+        // `and` binds tighter than `or`, `not` tighter than `and`.
+        assert!(eval("true or false and false"));
+        assert!(!eval("(true or false) and false"));
+        assert!(!eval("not false and false"));
+        assert!(eval("not (false and false)"));
+        assert!(eval("NOT false AND true"));
+        assert!(eval("TRUE or False"));
+        assert!(!eval("not True"));
+
+        let expr = parse_expr("true or false and not false", &[]).unwrap();
+        let Expr::Or(exprs) = expr else {
+            panic!("expected or, found {:?}", expr);
+        };
+        assert!(matches!(exprs[..], [Expr::Const(true), Expr::And(_)]));
+    }
+
+    #[test]
+    fn quoting_and_escapes() {
+        let tokens = tokenize(r#"'it\'s' "say \"hi\"" 'a\\b' '\d+' "it's""#).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Str("it's".to_string()),
+                Token::Str("say \"hi\"".to_string()),
+                Token::Str("a\\b".to_string()),
+                Token::Str("\\d+".to_string()),
+                Token::Str("it's".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokenize("score>=-3").unwrap(),
+            vec![
+                Token::Ident("score".to_string()),
+                Token::Op(">=".to_string()),
+                Token::Int(-3),
+            ]
+        );
+        assert!(tokenize("'open").is_err());
+        assert!(tokenize("'open\\").is_err());
+        assert!(tokenize("score = 1;").is_err());
+
+        assert!(eval(r"body ~ '^h\w+o$'"));
+        assert!(eval("subreddit == 'RUST'"));
+        assert!(eval("author in ['bob', 'Alice']"));
+    }
+
+    #[test]
+    fn time_literals() {
+        let expr = parse_expr("created_utc >= '2020-01-31'", &[]).unwrap();
+        assert!(matches!(
+            expr,
+            Expr::CmpInt(Field::CreatedUtc, CmpOp::Ge, 1580428800)
+        ));
+        let expr = parse_expr("created_utc < '2020-01-31T12:00:00+01:00'", &[]).unwrap();
+        assert!(matches!(
+            expr,
+            Expr::CmpInt(Field::CreatedUtc, CmpOp::Lt, 1580468400)
+        ));
+        assert!(parse_expr("created_utc > '2020-13-01'", &[]).is_err());
+
+        assert!(eval("created_utc > '2020-09-13'"));
+        assert!(!eval("created_utc > 1600000000"));
+    }
+
+    #[test]
+    fn type_errors() {
+        assert!(parse_expr("score = 'high'", &[]).is_err());
+        assert!(parse_expr("author > 3", &[]).is_err());
+        assert!(parse_expr("over_18 > true", &[]).is_err());
+        assert!(parse_expr("score in [1, 2]", &[]).is_err());
+        assert!(parse_expr("score ~ '1'", &[]).is_err());
+        assert!(parse_expr("karma > 1", &[]).is_err());
+        assert!(parse_expr("subreddit_list", &[]).is_err());
+        assert!(parse_expr("true true", &[]).is_err());
+        assert!(parse_expr("(true", &[]).is_err());
+        assert!(parse_expr("over_18 and score >= 10", &[]).is_ok());
+    }
+
+    #[test]
+    fn check_raw_agrees_with_check() {
+        let expressions = [
+            "subreddit_list",
+            "author_list",
+            "content_filter",
+            "subreddit_list or author_list",
+            "subreddit_list and author_list",
+            "not subreddit_list",
+            "subreddit == 'rust'",
+            "subreddit != 'rust'",
+            "author in ['alice', 'bob']",
+            "not author in ['alice']",
+            "subreddit_id == 't5_2qm4e'",
+            "(subreddit == 'rust' or content_filter) and score > 1",
+            "true",
+            "false",
+        ];
+        let lines = [
+            comment("askscience", "carol", "hello", 1),
+            comment("rust", "alice", "hello ferris", 5),
+            comment("Rust", "ALICE", "hello", 0),
+            comment("golang", "bob", "ferris", 3),
+            comment("golang", "dave", "nothing", 3),
+        ];
+
+        for expression in expressions {
+            let filter = filter(expression);
+            for line in &lines {
+                let record = RedditComment::try_from(line.as_str()).unwrap();
+                let selected = filter.check(&record).is_some();
+                match filter.expr.check_raw(line, &filter) {
+                    Maybe::No => assert!(!selected, "{} skipped {}", expression, line),
+                    Maybe::Yes => assert!(selected, "{} kept {}", expression, line),
+                    Maybe::Unknown => {}
+                }
+            }
+        }
+    }
+}
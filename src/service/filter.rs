@@ -0,0 +1,279 @@
+use crate::service::expr::{parse_expr, Expr, FilterRecord, NamedFilter};
+use crate::service::ContentFilter;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::RegexSet;
+use std::collections::HashSet;
+
+const REGEX_PREFIX: &str = "re:";
+const SUBREDDIT_ID_PREFIX: &str = "t5_";
+
+// Matches subreddit names against exact names, globs and regexes, and
+// subreddit ids against exact `t5_` ids.
+struct NameMatcher {
+    all: bool,
+    ids: HashSet<String>,
+    exact: HashSet<String>,
+    globs: GlobSet,
+    regexes: RegexSet,
+}
+
+impl NameMatcher {
+    fn new(patterns: &[String]) -> NameMatcher {
+        let mut all = false;
+        let mut ids = HashSet::new();
+        let mut exact = HashSet::new();
+        let mut globs = GlobSetBuilder::new();
+        let mut regexes = Vec::new();
+
+        for pattern in patterns {
+            if pattern == "*" {
+                all = true;
+            } else if pattern.to_lowercase().starts_with(SUBREDDIT_ID_PREFIX) {
+                ids.insert(pattern.to_lowercase());
+            } else if let Some(regex) = pattern.strip_prefix(REGEX_PREFIX) {
+                regexes.push(format!("(?i){}", regex));
+            } else if pattern.contains(['*', '?', '[', '{']) {
+                let glob = GlobBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .unwrap_or_else(|err| panic!("Invalid glob pattern {}: {}", pattern, err));
+                globs.add(glob);
+            } else {
+                exact.insert(pattern.to_lowercase());
+            }
+        }
+
+        NameMatcher {
+            all,
+            ids,
+            exact,
+            globs: globs
+                .build()
+                .unwrap_or_else(|err| panic!("Failed to build glob set: {}", err)),
+            regexes: RegexSet::new(&regexes)
+                .unwrap_or_else(|err| panic!("Invalid regex pattern: {}", err)),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.all
+            && self.ids.is_empty()
+            && self.exact.is_empty()
+            && self.globs.is_empty()
+            && self.regexes.is_empty()
+    }
+
+    // `name` must already be lowercase.
+    fn is_match(&self, name: &str) -> bool {
+        self.all
+            || self.exact.contains(name)
+            || self.globs.is_match(name)
+            || self.regexes.is_match(name)
+    }
+
+    // `id` must already be lowercase.
+    fn is_id_match(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+}
+
+/// Subreddit selection rules, compiled once and shared by the workers.
+///
+/// Patterns are exact names, globs such as `ask*`, regexes prefixed with
+/// `re:`, `t5_` subreddit ids, or `*` for all subreddits. Subreddits whose
+/// name or id match an exclude pattern are never selected. Matching is case
+/// insensitive.
+///
+/// Ids stay the same when a subreddit is renamed or its name is missing from
+/// a record, so they are the stable way to select a subreddit.
+pub struct SubredditFilter {
+    include: NameMatcher,
+    exclude: NameMatcher,
+}
+
+impl SubredditFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> SubredditFilter {
+        SubredditFilter {
+            include: NameMatcher::new(include),
+            exclude: NameMatcher::new(exclude),
+        }
+    }
+
+    /// Check if every subreddit is selected, so that there is nothing to
+    /// filter on.
+    pub fn is_match_all(&self) -> bool {
+        self.include.all && self.exclude.is_empty()
+    }
+
+    /// Check if the filter selects nothing.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty()
+    }
+
+    /// Check if the filter has any `t5_` id rules.
+    pub fn has_ids(&self) -> bool {
+        !self.include.ids.is_empty() || !self.exclude.ids.is_empty()
+    }
+
+    /// Check if a subreddit name alone is enough to select a record.
+    pub fn is_name_match(&self, subreddit: &str) -> bool {
+        let name = subreddit.to_lowercase();
+        self.include.is_match(&name) && !self.exclude.is_match(&name)
+    }
+
+    /// Check if a subreddit id alone is enough to select a record.
+    pub fn is_id_match(&self, subreddit_id: &str) -> bool {
+        let id = subreddit_id.to_lowercase();
+        self.include.is_id_match(&id) && !self.exclude.is_id_match(&id)
+    }
+
+    /// Check if a record of the given subreddit is selected.
+    pub fn is_match(&self, subreddit: &str, subreddit_id: Option<&str>) -> bool {
+        let name = subreddit.to_lowercase();
+        let id = subreddit_id.map(str::to_lowercase);
+        let id = id.as_deref();
+
+        let included =
+            self.include.is_match(&name) || id.is_some_and(|id| self.include.is_id_match(id));
+        let excluded =
+            self.exclude.is_match(&name) || id.is_some_and(|id| self.exclude.is_id_match(id));
+
+        included && !excluded
+    }
+}
+
+/// How the subreddit and author filters are combined.
+#[derive(serde::Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterCombine {
+    /// Select records matching any of the configured filters.
+    #[default]
+    Any,
+    /// Select records matching all of the configured filters.
+    All,
+}
+
+/// Why a record was selected.
+pub struct FilterMatch {
+    /// The filters that matched, e.g. `subreddit,author`.
+    pub matched_by: String,
+    /// The keywords and regex matches found by the content filter.
+    pub matched_terms: Vec<String>,
+}
+
+/// Record selection rules: subreddits, authors, content, and the expression
+/// combining them.
+///
+/// A filter with no rules is not configured and takes no part in the
+/// selection, so an author list alone selects by author only. Without a
+/// `filter` expression, the configured filters are combined according to
+/// `filter_combine`.
+pub struct RecordFilter {
+    pub subreddits: SubredditFilter,
+    pub authors: HashSet<String>,
+    pub content: ContentFilter,
+    pub expr: Expr,
+}
+
+impl RecordFilter {
+    pub fn new(
+        subreddits: SubredditFilter,
+        authors: &[String],
+        content: ContentFilter,
+        combine: FilterCombine,
+        expression: Option<&str>,
+    ) -> RecordFilter {
+        let mut filter = RecordFilter {
+            subreddits,
+            authors: authors.iter().map(|author| author.to_lowercase()).collect(),
+            content,
+            expr: Expr::Const(false),
+        };
+
+        let mut named = Vec::new();
+        if filter.has_subreddits() {
+            named.push(NamedFilter::SubredditList);
+        }
+        if filter.has_authors() {
+            named.push(NamedFilter::AuthorList);
+        }
+        if filter.has_content() {
+            named.push(NamedFilter::ContentFilter);
+        }
+
+        filter.expr = match expression {
+            Some(expression) => parse_expr(expression, &named)
+                .unwrap_or_else(|err| panic!("Invalid filter expression: {}", err)),
+            None => Expr::from_named(named, combine == FilterCombine::All),
+        };
+        log::info!("Filter expression: {:?}", filter.expr);
+
+        filter
+    }
+
+    /// Check if the subreddit filter has any rules.
+    pub fn has_subreddits(&self) -> bool {
+        !self.subreddits.is_empty()
+    }
+
+    /// Check if the author filter has any rules.
+    pub fn has_authors(&self) -> bool {
+        !self.authors.is_empty()
+    }
+
+    /// Check if the content filter has any rules.
+    pub fn has_content(&self) -> bool {
+        !self.content.is_empty()
+    }
+
+    /// Check if an author is selected.
+    pub fn is_author_match(&self, author: &str) -> bool {
+        self.authors.contains(&author.to_lowercase())
+    }
+
+    /// Check if a record is selected, and why.
+    pub fn check(&self, record: &dyn FilterRecord) -> Option<FilterMatch> {
+        let subreddit_match = self.has_subreddits().then(|| {
+            self.subreddits
+                .is_match(record.subreddit(), record.subreddit_id())
+        });
+        let author_match = self
+            .has_authors()
+            .then(|| self.is_author_match(record.author()));
+        let matched_terms = if self.has_content() {
+            self.content.find_terms(&record.texts())
+        } else {
+            vec![]
+        };
+        let content_match = self.has_content().then_some(!matched_terms.is_empty());
+
+        let selected = self.expr.eval(record, &mut |named| match named {
+            NamedFilter::SubredditList => subreddit_match == Some(true),
+            NamedFilter::AuthorList => author_match == Some(true),
+            NamedFilter::ContentFilter => content_match == Some(true),
+        });
+        if !selected {
+            return None;
+        }
+
+        let mut tags = Vec::new();
+        if subreddit_match == Some(true) {
+            tags.push("subreddit");
+        }
+        if author_match == Some(true) {
+            tags.push("author");
+        }
+        if content_match == Some(true) {
+            tags.push("content");
+        }
+        // Selected by the field tests of the expression alone.
+        if tags.is_empty() {
+            tags.push("filter");
+        }
+
+        Some(FilterMatch {
+            matched_by: tags.join(","),
+            matched_terms,
+        })
+    }
+}
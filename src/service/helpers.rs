@@ -22,6 +22,18 @@ struct FileProgress {
     total_read: u64,
     is_done: bool,
     fname: String,
+    #[serde(default)]
+    failure: Option<FileFailure>,
+    #[serde(default)]
+    invalid_utf8_lines: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// Why reading a file stopped early, and where.
+struct FileFailure {
+    error: String,
+    last_good_line: u64,
+    last_good_offset: u64,
 }
 
 impl FileProgress {
@@ -31,8 +43,15 @@ impl FileProgress {
             total_read: 0,
             is_done: false,
             fname: fname.to_string(),
+            failure: None,
+            invalid_utf8_lines: 0,
         }
     }
+
+    // Offset in the decompressed data, counting the line separators.
+    fn offset(&self) -> u64 {
+        self.total_read + self.total_lines
+    }
 }
 
 impl TotalProgress {
@@ -43,7 +62,7 @@ impl TotalProgress {
 
             data.log_frequency = log_frequency;
 
-            return data;
+            data
         } else {
             TotalProgress {
                 files: HashMap::<String, FileProgress>::new(),
@@ -69,12 +88,12 @@ impl TotalProgress {
     }
 
     /// Update the progress of a file.
-    pub fn update_file(&mut self, fname: &str, line: &str) -> bool {
+    pub fn update_file(&mut self, fname: &str, line: &[u8]) -> bool {
         let file = self.files.get_mut(fname).unwrap();
 
         file.total_lines += 1;
         file.total_read += line.len() as u64;
-        if file.total_lines % self.log_frequency == 0 {
+        if file.total_lines.is_multiple_of(self.log_frequency) {
             log::info!(
                 "[{}] Processed {} lines, {} GB",
                 fname,
@@ -89,31 +108,112 @@ impl TotalProgress {
         false
     }
 
+    /// Move the progress of a file back to an earlier line.
+    pub fn rewind_file(&mut self, fname: &str, total_lines: u64, total_read: u64) {
+        let file = self.files.get_mut(fname).unwrap();
+        file.total_lines = total_lines;
+        file.total_read = total_read;
+    }
+
+    /// Count a line that is not valid UTF-8.
+    pub fn add_invalid_utf8_line(&mut self, fname: &str) {
+        let file = self.files.get_mut(fname).unwrap();
+        file.invalid_utf8_lines += 1;
+    }
+
     /// Finish processing a file.
     pub fn finish_file(&mut self, fname: &str) {
         let file = self.files.get_mut(fname).unwrap();
         file.is_done = true;
+        file.failure = None;
         log::info!(
-            "[{}] Finished processing {} lines, {} GB",
+            "[{}] Finished processing {} lines, {} GB, {} invalid UTF-8 lines",
+            fname,
+            file.total_lines,
+            bytes_to_gb(file.total_read),
+            file.invalid_utf8_lines
+        );
+
+        self.save_to_file();
+    }
+
+    /// Mark a file as failed, keeping the position of the last good line.
+    ///
+    /// Failed files are not done, so they are retried on the next run.
+    pub fn fail_file(&mut self, fname: &str, error: &str) {
+        let file = self.files.get_mut(fname).unwrap();
+        file.is_done = false;
+        file.failure = Some(FileFailure {
+            error: error.to_string(),
+            last_good_line: file.total_lines,
+            last_good_offset: file.offset(),
+        });
+        log::error!(
+            "[{}] Failed after {} lines, offset {}: {}",
             fname,
             file.total_lines,
-            bytes_to_gb(file.total_read)
+            file.offset(),
+            error
         );
 
         self.save_to_file();
     }
 
+    /// Print the state of all the known files.
+    pub fn print_status(&self) {
+        let mut files: Vec<&FileProgress> = self.files.values().collect();
+        files.sort_by(|a, b| a.fname.cmp(&b.fname));
+
+        for file in files {
+            let state = if file.is_done {
+                "done"
+            } else if file.failure.is_some() {
+                "failed"
+            } else {
+                "partial"
+            };
+
+            println!(
+                "{:<8} {} ({} lines, {} GB, {} invalid UTF-8 lines)",
+                state,
+                file.fname,
+                file.total_lines,
+                bytes_to_gb(file.total_read),
+                file.invalid_utf8_lines
+            );
+
+            if let Some(failure) = &file.failure {
+                println!(
+                    "         last good line {}, offset {}: {}",
+                    failure.last_good_line, failure.last_good_offset, failure.error
+                );
+            }
+        }
+    }
+
     /// Get the total lines read for a file.
     pub fn total_lines_file(&self, fname: &str) -> u64 {
         let file = self.files.get(fname).unwrap();
         file.total_lines
     }
 
+    /// Get the total bytes read for a file, without the line separators.
+    pub fn total_read_file(&self, fname: &str) -> u64 {
+        let file = self.files.get(fname).unwrap();
+        file.total_read
+    }
+
     /// Check if a file is done.
     pub fn is_file_done(&self, fname: &str) -> bool {
         let file = self.files.get(fname).unwrap();
         file.is_done
     }
+
+    /// Check if a file failed on a previous run.
+    pub fn is_file_failed(&self, fname: &str) -> bool {
+        let file = self.files.get(fname).unwrap();
+        file.failure.is_some()
+    }
 }
 
 // Convert bytes to gigabytes.
@@ -125,8 +225,9 @@ pub fn bytes_to_gb(bytes: u64) -> f64 {
 }
 
 // Debug a submission line.
+#[allow(dead_code)]
 pub fn debug_submission(line: &str) {
-    let data: Value = match serde_json::from_str(&line) {
+    let data: Value = match serde_json::from_str(line) {
         Ok(v) => v,
         Err(e) => {
             panic!("Error: {} for {}", e, line);
@@ -144,7 +245,7 @@ pub fn debug_submission(line: &str) {
 
             // For each line in pretty_json print the line number and the line.
             for (mut i, line) in pretty_json.lines().enumerate() {
-                i = i + 1;
+                i += 1;
                 let i = i as u64;
                 let error_line = error_line as u64;
 
@@ -166,8 +267,9 @@ pub fn debug_submission(line: &str) {
     };
 }
 
+#[allow(dead_code)]
 pub fn debug_comment(line: &str) {
-    let data: Value = match serde_json::from_str(&line) {
+    let data: Value = match serde_json::from_str(line) {
         Ok(v) => v,
         Err(e) => {
             panic!("Error: {} for {}", e, line);
@@ -185,7 +287,7 @@ pub fn debug_comment(line: &str) {
 
             // For each line in pretty_json print the line number and the line.
             for (mut i, line) in pretty_json.lines().enumerate() {
-                i = i + 1;
+                i += 1;
 
                 let i = i as i64;
                 let error_line = error_line as i64;
@@ -10,7 +10,10 @@ mod db_submission;
 pub use db_submission::DBRedditSubmission;
 
 mod db_comments;
-pub use db_comments::DBRedditComment;
+pub use db_comments::{resolved_parent_ids, DBRedditComment};
+
+mod db_comments_full;
+pub use db_comments_full::DBRedditCommentFull;
 
 mod db_submission_small;
 pub use db_submission_small::DBRedditSubmissionSmall;
@@ -18,11 +21,56 @@ pub use db_submission_small::DBRedditSubmissionSmall;
 mod db_mgr;
 pub use db_mgr::{DBManager, InsertStrategy};
 
+mod error;
+pub use error::ErrorPolicy;
+
 mod cfg;
-pub use cfg::read_json_config;
+pub use cfg::{read_json_config, Utf8Mode};
+
+mod discovery;
+pub use discovery::discover_files;
+
+mod torrent;
+pub use torrent::{verify_dumps, VerifyPolicy};
 
 mod db_object;
 pub use db_object::DBTable;
 
+mod columns;
+pub use columns::set_column_selection;
+
+mod rejected;
+pub use rejected::RejectedLines;
+
+mod content;
+pub use content::ContentFilter;
+
+mod expr;
+
+mod filter;
+pub use filter::{FilterCombine, RecordFilter, SubredditFilter};
+
+mod prefilter;
+
+mod base36;
+
+mod sample;
+
+mod threshold;
+pub use threshold::Thresholds;
+
+mod schema;
+pub use schema::check_schema;
+
+mod tree;
+pub use tree::build_comment_trees;
+
+mod context;
+pub use context::complete_context;
+
+mod resolve;
+pub use resolve::resolve_subreddit_ids;
+
 mod helpers;
+pub use helpers::TotalProgress;
 mod raw_object;
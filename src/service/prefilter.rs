@@ -0,0 +1,106 @@
+use crate::service::base36;
+use crate::service::expr::Maybe;
+use crate::service::{RecordFilter, SubredditFilter};
+use memchr::memmem;
+use std::collections::HashSet;
+
+const SUBREDDIT_KEY: &[u8] = b"\"subreddit\"";
+const SUBREDDIT_ID_KEY: &[u8] = b"\"subreddit_id\"";
+const AUTHOR_KEY: &[u8] = b"\"author\"";
+const ID_KEY: &[u8] = b"\"id\"";
+
+// Read the JSON string value following a key, e.g. `: "science"`.
+// Returns None for anything unexpected, like escaped characters.
+fn string_value(rest: &[u8]) -> Option<&str> {
+    let rest = rest.trim_ascii_start().strip_prefix(b":")?;
+    let rest = rest.trim_ascii_start().strip_prefix(b"\"")?;
+    let end = memchr::memchr2(b'"', b'\\', rest)?;
+
+    if rest[end] != b'"' {
+        return None;
+    }
+
+    std::str::from_utf8(&rest[..end]).ok()
+}
+
+// Read the values of every occurrence of `key`.
+// Returns None if any of them cannot be read.
+fn string_values<'a>(bytes: &'a [u8], key: &[u8]) -> Option<Vec<&'a str>> {
+    memmem::find_iter(bytes, key)
+        .map(|pos| string_value(&bytes[pos + key.len()..]))
+        .collect()
+}
+
+/// Cheap check of the `"subreddit"` and `"subreddit_id"` values of a raw
+/// JSON line.
+///
+/// Returns false only when the line certainly does not belong to any of the
+/// subreddits selected by `filter`. Lines that cannot be checked this way,
+/// because the field is missing or escaped, return true and are left to the
+/// full deserialization.
+///
+/// Every `"subreddit"` key is checked, since nested objects such as
+/// `crosspost_parent_list` carry their own.
+pub fn may_match_subreddit(line: &str, filter: &SubredditFilter) -> bool {
+    if filter.is_match_all() {
+        return true;
+    }
+
+    let bytes = line.as_bytes();
+    let Some(names) = string_values(bytes, SUBREDDIT_KEY) else {
+        return true;
+    };
+    if names.is_empty() || names.iter().any(|name| filter.is_name_match(name)) {
+        return true;
+    }
+
+    if !filter.has_ids() {
+        return false;
+    }
+
+    let Some(ids) = string_values(bytes, SUBREDDIT_ID_KEY) else {
+        return true;
+    };
+    ids.is_empty() || ids.iter().any(|id| filter.is_id_match(id))
+}
+
+/// Cheap check of the `"author"` values of a raw JSON line, with the same
+/// guarantees as `may_match_subreddit`.
+pub fn may_match_author(line: &str, filter: &RecordFilter) -> bool {
+    let Some(authors) = string_values(line.as_bytes(), AUTHOR_KEY) else {
+        return true;
+    };
+
+    authors.is_empty() || authors.iter().any(|author| filter.is_author_match(author))
+}
+
+/// Cheap check of the values of `key` in a raw JSON line against a list of
+/// lowercase strings, with the same guarantees as `may_match_subreddit`.
+pub fn may_equal(line: &str, key: &[u8], values: &[String]) -> bool {
+    let Some(found) = string_values(line.as_bytes(), key) else {
+        return true;
+    };
+
+    found.is_empty()
+        || found
+            .iter()
+            .any(|value| values.contains(&value.to_lowercase()))
+}
+
+/// Cheap check of the `"id"` values of a raw JSON line against a set of
+/// numeric ids, with the same guarantees as `may_match_subreddit`.
+pub fn may_have_id(line: &str, ids: &HashSet<i64>) -> bool {
+    let Some(found) = string_values(line.as_bytes(), ID_KEY) else {
+        return true;
+    };
+
+    found.is_empty()
+        || found
+            .iter()
+            .any(|id| base36::decode(id).is_some_and(|id| ids.contains(&id)))
+}
+
+/// Cheap check of a raw JSON line against all the rules of `filter`.
+pub fn may_match(line: &str, filter: &RecordFilter) -> bool {
+    filter.expr.check_raw(line, filter) != Maybe::No
+}
@@ -1,101 +1,304 @@
+use crate::service::cfg::Parser;
 use crate::service::db_object::DBObject;
+use crate::service::error::{Error, ErrorPolicy, Result};
 use crate::service::helpers::TotalProgress;
-use crate::service::InsertStrategy;
+use crate::service::prefilter::may_match;
+use crate::service::torrent::{VerifyPolicy, VerifyRecord, VerifyStatus};
+use crate::service::{InsertStrategy, RecordFilter, RejectedLines, Thresholds, Utf8Mode};
 use async_channel::{Receiver, Sender};
 use sqlx::pool;
-use std::collections::HashMap;
 use std::process::Stdio;
+use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 
 use super::raw_comment::RedditComment;
-use super::{DBRedditComment, DBRedditSubmission, RedditSubmission};
+use super::{DBRedditComment, DBRedditCommentFull, DBRedditSubmission, RedditSubmission};
 
-async fn sub_consume_line(
+/// Build the command decompressing a dump file to stdout.
+pub fn zstd_command(fname: &str) -> Command {
+    let mut cmd = Command::new("zstd");
+    cmd.arg("--memory=2048MB").arg("-d").arg("-c").arg(fname);
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    cmd
+}
+
+/// Wait for a zstd process whose stdout was read, logging its stderr if it
+/// failed.
+pub async fn wait_zstd(fname: &str, child: Child) {
+    let output = child
+        .wait_with_output()
+        .await
+        .expect("Failed to wait for zstd process");
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        log::error!("[{}] zstd {}: {}", fname, output.status, stderr.trim());
+    }
+}
+
+// Signal that a line was processed.
+async fn send_done(channel: &Sender<()>) {
+    match channel.send(()).await {
+        Ok(_) => {}
+        Err(e) => {
+            println!("Error: {}", e);
+        }
+    }
+}
+
+// A line to parse, with its position in the file.
+struct Line {
+    number: u64,
+    // Bytes of the lines before this one, without the separators.
+    read_before: u64,
+    text: String,
+}
+
+// A record parsed and selected for insertion.
+enum ParsedRecord {
+    Submission(DBRedditSubmission),
+    Comment(DBRedditComment),
+    FullComment(Box<DBRedditCommentFull>),
+}
+
+impl ParsedRecord {
+    async fn insert(&self, pool: &pool::Pool<sqlx::Postgres>) -> Result<()> {
+        match self {
+            ParsedRecord::Submission(submission) => {
+                submission
+                    .insert(pool, InsertStrategy::InsertKeepContent, false)
+                    .await
+            }
+            ParsedRecord::Comment(comment) => {
+                comment
+                    .insert(pool, InsertStrategy::InsertKeepContent, false)
+                    .await
+            }
+            ParsedRecord::FullComment(comment) => {
+                comment
+                    .insert(pool, InsertStrategy::InsertKeepContent, false)
+                    .await
+            }
+        }
+    }
+}
+
+// A parsed record with its line, kept in case the insert fails.
+struct ParsedLine {
+    line: Line,
+    record: ParsedRecord,
+}
+
+// Why a file was aborted, and where to retry it from.
+struct Aborted {
+    error: Error,
+    // The failed line.
+    line: u64,
+    // The first line that was not imported, with the bytes before it.
+    retry_from: u64,
+    read_before: u64,
+}
+
+// Handles the lines that failed to parse or insert, according to the error
+// policy. Shared by the workers of a file.
+#[derive(Clone)]
+struct FailedLines {
+    fname: String,
+    policy: ErrorPolicy,
+    rejected: RejectedLines,
+    // Several workers can fail at once, the first failed line is kept.
+    aborted: Arc<Mutex<Option<Aborted>>>,
+}
+
+impl FailedLines {
+    // Returns false when the file must be aborted.
+    fn handle(&self, line: &Line, err: Error) -> bool {
+        // A lost connection is not caused by the line: the file is retried
+        // whatever the policy.
+        if self.policy == ErrorPolicy::Abort || err.is_connection() {
+            log::error!("[{}] Line {}: {}", self.fname, line.number, err);
+            let mut aborted = self.aborted.lock().unwrap();
+            match aborted.as_mut() {
+                None => {
+                    *aborted = Some(Aborted {
+                        error: err,
+                        line: line.number,
+                        retry_from: line.number,
+                        read_before: line.read_before,
+                    })
+                }
+                Some(first) => {
+                    if line.number < first.line {
+                        first.error = err;
+                        first.line = line.number;
+                    }
+                    first.retry_from(line);
+                }
+            }
+            return false;
+        }
+
+        self.rejected.write(
+            &self.fname,
+            line.number,
+            &err.to_string(),
+            line.text.as_bytes(),
+        );
+        true
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.aborted.lock().unwrap().is_some()
+    }
+
+    // Drop a line queued when the file was aborted. It is imported when the
+    // file is retried.
+    fn skip(&self, line: &Line) {
+        if let Some(aborted) = self.aborted.lock().unwrap().as_mut() {
+            aborted.retry_from(line);
+        }
+    }
+}
+
+impl Aborted {
+    // Retry from `line` if it comes first.
+    fn retry_from(&mut self, line: &Line) {
+        if line.number < self.retry_from {
+            self.retry_from = line.number;
+            self.read_before = line.read_before;
+        }
+    }
+}
+
+// Parse a submission line. Runs on the blocking thread pool.
+fn sub_consume_line(
     line: &str,
-    subreddit_list: &HashMap<String, bool>,
+    record_filter: &RecordFilter,
+    thresholds: &Thresholds,
+    error_policy: ErrorPolicy,
     ingestion_w_summarized_db: bool,
-    pool: &pool::Pool<sqlx::Postgres>,
-    channel: &Sender<()>,
-) {
+) -> Result<Option<ParsedRecord>> {
     // Remove leading \0 characters.
     let line = line.trim_start_matches(char::from(0));
 
-    // deserialize the line into a JSON object
-    let json = RedditSubmission::from(line);
-
-    let subreddit_name = json.subreddit.to_lowercase();
-    if subreddit_list.contains_key(&subreddit_name) {
-        let db_submission = DBRedditSubmission::from(&json);
-        db_submission
-            .insert(&pool, InsertStrategy::InsertIgnore, true)
-            .await;
+    // Skip the full deserialization for lines that cannot be selected.
+    if !may_match(line, record_filter) {
+        return Ok(None);
     }
 
+    // deserialize the line into a JSON object
+    let json = RedditSubmission::try_from(line)?;
+
     if ingestion_w_summarized_db {
         // let db_submission_small = ObjSmallTable::from(&json);
         // db_submission_small
-        //     .insert(&pool, InsertStrategy::InsertIgnore, true)
+        //     .insert(pool, InsertStrategy::InsertIgnore, true)
         //     .await;
     }
 
-    match channel.send(()).await {
-        Ok(_) => {}
-        Err(e) => {
-            println!("Error: {}", e);
-        }
+    let Some(matched) = record_filter.check(&json) else {
+        return Ok(None);
+    };
+    if !thresholds.submissions.keep(&json) {
+        return Ok(None);
     }
+
+    let mut db_submission = DBRedditSubmission::new(&json, error_policy)?;
+    db_submission.matched_by = matched.matched_by;
+    db_submission.matched_terms = matched.matched_terms;
+    Ok(Some(ParsedRecord::Submission(db_submission)))
 }
 
-async fn com_consume_line(
+// Parse a comment line. Runs on the blocking thread pool.
+fn com_consume_line(
     line: &str,
-    subreddit_list: &HashMap<String, bool>,
+    record_filter: &RecordFilter,
+    thresholds: &Thresholds,
+    error_policy: ErrorPolicy,
     ingestion_w_summarized_db: bool,
-    pool: &pool::Pool<sqlx::Postgres>,
-    channel: &Sender<()>,
-) {
+    full_comments: bool,
+) -> Result<Option<ParsedRecord>> {
     // Remove leading \0 characters.
     let line = line.trim_start_matches(char::from(0));
 
-    // deserialize the line into a JSON object
-    let json = RedditComment::from(line);
-
-    let subreddit_name = json.subreddit.to_lowercase();
-
-    if subreddit_list.contains_key(&subreddit_name) {
-        let db_submission = DBRedditComment::from(&json);
-        db_submission
-            .insert(&pool, InsertStrategy::InsertIgnore, true)
-            .await;
+    // Skip the full deserialization for lines that cannot be selected.
+    if !may_match(line, record_filter) {
+        return Ok(None);
     }
 
+    // deserialize the line into a JSON object
+    let json = RedditComment::try_from(line)?;
+
     if ingestion_w_summarized_db {
         // let db_submission_small = ObjSmallTable::from(&json);
         // db_submission_small
-        //     .insert(&pool, InsertStrategy::InsertIgnore, true)
+        //     .insert(pool, InsertStrategy::InsertIgnore, true)
         //     .await;
     }
 
-    match channel.send(()).await {
-        Ok(_) => {}
-        Err(e) => {
-            println!("Error: {}", e);
-        }
+    let Some(matched) = record_filter.check(&json) else {
+        return Ok(None);
+    };
+    if !thresholds.comments.keep(&json) {
+        return Ok(None);
     }
+
+    if full_comments {
+        let mut db_comment = DBRedditCommentFull::new(&json, error_policy)?;
+        db_comment.comment.matched_by = matched.matched_by;
+        db_comment.comment.matched_terms = matched.matched_terms;
+        return Ok(Some(ParsedRecord::FullComment(Box::new(db_comment))));
+    }
+
+    let mut db_comment = DBRedditComment::new(&json, error_policy)?;
+    db_comment.matched_by = matched.matched_by;
+    db_comment.matched_terms = matched.matched_terms;
+    Ok(Some(ParsedRecord::Comment(db_comment)))
 }
 
+/// Import a dump file.
+///
+/// Returns an error when the file was aborted by the `abort` error policy.
+#[allow(clippy::too_many_arguments)]
 pub async fn consume_file(
     fname: &str,
     log_frequency: u64,
-    num_workers: u64,
-    qsize_to_parse: u64,
-    subreddit_list: HashMap<String, bool>,
+    parser: &Parser,
+    record_filter: Arc<RecordFilter>,
+    thresholds: Arc<Thresholds>,
     ingestion_w_summarized_db: bool,
+    full_comments: bool,
+    verify_policy: VerifyPolicy,
+    rejected: &RejectedLines,
     pool: pool::Pool<sqlx::Postgres>,
     channel: Sender<()>,
-) {
+) -> Result<()> {
     log::info!("Processing file: {}", fname);
 
+    // Check the result of `verify-dumps` for this file.
+    if verify_policy != VerifyPolicy::Ignore {
+        match VerifyRecord::load().get(fname) {
+            Some(verified) if verified.status == VerifyStatus::Failed => {
+                let error = verified.error.clone().unwrap_or_default();
+                if verify_policy == VerifyPolicy::Refuse {
+                    log::error!(
+                        "[{}] Refusing file that failed verification: {}",
+                        fname,
+                        error
+                    );
+                    return Ok(());
+                }
+                log::warn!("[{}] File failed verification: {}", fname, error);
+            }
+            Some(verified) if verified.status == VerifyStatus::Unverified => {
+                log::info!("[{}] File could not be verified", fname)
+            }
+            Some(_) => {}
+            None => log::info!("[{}] File has not been verified", fname),
+        }
+    }
+
     // Create a progress tracker for the file.
     let mut fprogress: TotalProgress = TotalProgress::new(log_frequency);
     fprogress.add_file(fname);
@@ -103,92 +306,230 @@ pub async fn consume_file(
     // Check if the file has already been processed.
     if fprogress.is_file_done(fname) {
         log::info!("File already processed: {}", fname);
-        return;
+        return Ok(());
     }
 
-    // Create a pipe to read the decompressed data.
-    let mut cmd = Command::new("zstd");
-    cmd.arg("--memory=2048MB").arg("-d").arg("-c").arg(fname);
-    cmd.stdout(Stdio::piped());
+    if fprogress.is_file_failed(fname) {
+        log::info!("Retrying previously failed file: {}", fname);
+    }
 
+    // Create a pipe to read the decompressed data.
+    let mut cmd = zstd_command(fname);
     let mut child = cmd.spawn().expect("Failed to spawn zstd process");
     let stdout = child.stdout.take().expect("Failed to open stdout");
-    let mut reader = BufReader::new(stdout).lines();
+    let mut reader = BufReader::new(stdout);
 
     // Ensure the child process is spawned in the runtime so it can
     // make progress on its own while we await for any output.
-    tokio::spawn(async move {
-        let status = child
-            .wait()
+    // The exit status and stderr tell whether the archive was complete.
+    let child_output = tokio::spawn(async move {
+        let output = child
+            .wait_with_output()
             .await
             .expect("child process encountered an error");
 
-        log::info!("child status was: {}", status);
+        log::info!("child status was: {}", output.status);
+        output
     });
 
-    let (sender_line, receiver_line): (Sender<String>, Receiver<String>) =
-        async_channel::bounded(qsize_to_parse as usize);
+    // Pipeline: zstd decompresses, this task splits the lines, the parse
+    // workers deserialize them on the blocking thread pool and the insert
+    // workers push the selected rows to the database.
+    let (sender_line, receiver_line): (Sender<Line>, Receiver<Line>) =
+        async_channel::bounded(parser.qsize_to_parse as usize);
+    let (sender_parsed, receiver_parsed): (Sender<ParsedLine>, Receiver<ParsedLine>) =
+        async_channel::bounded(parser.qsize_to_insert as usize);
 
-    for _ in 0..num_workers {
+    let failed = FailedLines {
+        fname: fname.to_string(),
+        policy: parser.error_policy,
+        rejected: rejected.clone(),
+        aborted: Arc::new(Mutex::new(None)),
+    };
+
+    let is_submission = fname.contains("RS_");
+    let error_policy = parser.error_policy;
+    let mut parse_workers = Vec::new();
+    for _ in 0..parser.parse_workers {
         let receiver_line = receiver_line.clone();
+        let sender_parsed = sender_parsed.clone();
         let channel = channel.clone();
-        let subreddit_list = subreddit_list.clone();
-        let pool = pool.clone();
+        let record_filter = record_filter.clone();
+        let thresholds = thresholds.clone();
+        let failed = failed.clone();
 
-        if fname.contains("RS_") {
-            tokio::spawn(async move {
-                while let Ok(line) = receiver_line.recv().await {
+        parse_workers.push(tokio::task::spawn_blocking(move || {
+            while let Ok(line) = receiver_line.recv_blocking() {
+                // Empty the queue once the file is aborted.
+                if failed.is_aborted() {
+                    failed.skip(&line);
+                    continue;
+                }
+
+                let parsed = if is_submission {
                     sub_consume_line(
-                        &line,
-                        &subreddit_list,
+                        &line.text,
+                        &record_filter,
+                        &thresholds,
+                        error_policy,
                         ingestion_w_summarized_db,
-                        &pool,
-                        &channel,
                     )
-                    .await;
-                }
-
-                log::info!("Subreddit consumer done");
-            });
-        } else {
-            tokio::spawn(async move {
-                while let Ok(line) = receiver_line.recv().await {
+                } else {
                     com_consume_line(
-                        &line,
-                        &subreddit_list,
+                        &line.text,
+                        &record_filter,
+                        &thresholds,
+                        error_policy,
                         ingestion_w_summarized_db,
-                        &pool,
-                        &channel,
+                        full_comments,
                     )
-                    .await;
+                };
+
+                // Lines that are not inserted are done here.
+                let sent = match parsed {
+                    Ok(Some(record)) => sender_parsed
+                        .send_blocking(ParsedLine { line, record })
+                        .is_ok(),
+                    Ok(None) => channel.send_blocking(()).is_ok(),
+                    Err(err) => {
+                        if !failed.handle(&line, err) {
+                            // Stop reading the file.
+                            receiver_line.close();
+                            continue;
+                        }
+                        channel.send_blocking(()).is_ok()
+                    }
+                };
+                if !sent {
+                    log::error!("Parse worker output queue closed");
+                    break;
                 }
+            }
 
-                log::info!("Comment consumer done");
-            });
-        }
+            log::info!("Parse worker done");
+        }));
+    }
+    drop(sender_parsed);
+
+    // The queued records are not dropped on abort, they are skipped so that
+    // the file is retried from the first one.
+    let mut insert_workers = Vec::new();
+    for _ in 0..parser.db_workers {
+        let receiver_line = receiver_line.clone();
+        let receiver_parsed = receiver_parsed.clone();
+        let channel = channel.clone();
+        let pool = pool.clone();
+        let failed = failed.clone();
+
+        insert_workers.push(tokio::spawn(async move {
+            while let Ok(parsed) = receiver_parsed.recv().await {
+                if failed.is_aborted() {
+                    failed.skip(&parsed.line);
+                    continue;
+                }
+
+                if let Err(err) = parsed.record.insert(&pool).await {
+                    if !failed.handle(&parsed.line, err) {
+                        // Stop reading the file.
+                        receiver_line.close();
+                        continue;
+                    }
+                }
+                send_done(&channel).await;
+            }
+
+            log::info!("Insert worker done");
+        }));
     }
+    drop(receiver_line);
+    drop(receiver_parsed);
 
+    // Lines are read as bytes so that invalid UTF-8 does not end the file.
     let mut line_count = 0;
-    while let Ok(Some(line)) = reader.next_line().await {
+    let mut read_error = None;
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                read_error = Some(Error::Io(err).to_string());
+                break;
+            }
+        };
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+        }
         line_count += 1;
 
-        if line_count < fprogress.total_lines_file(fname) {
+        if line_count <= fprogress.total_lines_file(fname) {
             continue;
         }
-        sender_line.send(line.clone()).await.unwrap();
 
-        if fprogress.update_file(fname, &line) {
+        let text = match std::str::from_utf8(&buf) {
+            Ok(line) => Some(line.to_string()),
+            Err(err) => {
+                // Invalid lines are always recorded, even when imported.
+                fprogress.add_invalid_utf8_line(fname);
+                let reason = match parser.utf8_mode {
+                    Utf8Mode::Strict => format!("invalid UTF-8: {}", err),
+                    Utf8Mode::Lossy => format!("invalid UTF-8, imported lossy: {}", err),
+                };
+                rejected.write(fname, line_count, &reason, &buf);
+
+                (parser.utf8_mode == Utf8Mode::Lossy)
+                    .then(|| String::from_utf8_lossy(&buf).into_owned())
+            }
+        };
+
+        // The workers only close the queue when the file is aborted.
+        if let Some(text) = text {
+            let line = Line {
+                number: line_count,
+                read_before: fprogress.total_read_file(fname),
+                text,
+            };
+            if sender_line.send(line).await.is_err() {
+                break;
+            }
+        }
+
+        if fprogress.update_file(fname, &buf) {
             log::info!("Queue sender {}", sender_line.len(),);
         }
     }
+    rejected.flush();
 
-    fprogress.finish_file(fname);
-
-    // Wait for all the consumers to finish.
-    while !receiver_line.is_empty() {
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    // Drain the decompressor so a truncated or corrupt archive is noticed.
+    drop(reader);
+    let output = child_output.await.expect("Failed to wait for zstd process");
+    if read_error.is_none() && !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        read_error = Some(format!("zstd {}: {}", output.status, stderr.trim()));
     }
 
+    // Wait for all the queued lines to be parsed and inserted.
     drop(sender_line);
-    drop(receiver_line);
+    for worker in parse_workers {
+        worker.await.expect("Parse worker panicked");
+    }
+    for worker in insert_workers {
+        worker.await.expect("Insert worker panicked");
+    }
+
+    rejected.flush();
+
+    // Retry from the first line that was not imported on the next run.
+    if let Some(aborted) = failed.aborted.lock().unwrap().take() {
+        fprogress.rewind_file(fname, aborted.retry_from - 1, aborted.read_before);
+        fprogress.fail_file(fname, &format!("line {}: {}", aborted.line, aborted.error));
+        return Err(aborted.error);
+    }
+
+    match read_error {
+        Some(error) => fprogress.fail_file(fname, &error),
+        None => fprogress.finish_file(fname),
+    }
+    Ok(())
 }
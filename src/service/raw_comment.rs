@@ -1,6 +1,8 @@
+use serde::de::Error as _;
 use serde::{Deserialize, Deserializer};
 
-use super::helpers::debug_comment;
+use super::base36;
+use super::error::{Error, Result};
 use super::raw_object::AnyTimestamp;
 
 #[derive(Debug)]
@@ -10,27 +12,42 @@ pub enum ParentId {
 }
 
 impl<'de> Deserialize<'de> for ParentId {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         let value = serde_json::Value::deserialize(deserializer)?;
         match value {
             serde_json::Value::String(string_value) => Ok(ParentId::String(string_value)),
-            serde_json::Value::Number(int_value) => {
-                let int_value = int_value.as_i64().unwrap();
-                Ok(ParentId::Int(int_value))
-            }
-            _ => panic!("expected string or int, found {:?}", value),
+            serde_json::Value::Number(num) => match num.as_i64() {
+                Some(int_value) => Ok(ParentId::Int(int_value)),
+                None => Err(D::Error::custom(format!("expected int id, found {}", num))),
+            },
+            _ => Err(D::Error::custom(format!(
+                "expected string or int id, found {}",
+                value
+            ))),
         }
     }
 }
 
-impl From<&ParentId> for String {
-    fn from(parent_id: &ParentId) -> Self {
-        match parent_id {
-            ParentId::String(string_value) => string_value.clone(),
-            ParentId::Int(int_value) => format!("unknown-{}", int_value),
+impl ParentId {
+    /// The parent as a fullname. Integer ids from old dumps are encoded in
+    /// base 36, as `t3_` when they are the submission of `link_id` and as
+    /// `t1_` otherwise.
+    pub fn to_fullname(&self, link_id: &str) -> Result<String> {
+        match self {
+            ParentId::String(string_value) => Ok(string_value.clone()),
+            ParentId::Int(int_value) => {
+                let id = u64::try_from(*int_value)
+                    .map_err(|_| Error::Parse(format!("invalid parent id {}", int_value)))?;
+                let kind = if base36::decode(link_id) == Some(*int_value) {
+                    "t3"
+                } else {
+                    "t1"
+                };
+                Ok(format!("{}_{}", kind, base36::encode(id)))
+            }
         }
     }
 }
@@ -121,18 +138,44 @@ pub struct RedditComment {
     pub user_reports_dismissed: Option<serde_json::Value>,
 }
 
-impl From<&str> for RedditComment {
-    fn from(s: &str) -> Self {
-        let result_or_err = serde_json::from_str(s);
+impl TryFrom<&str> for RedditComment {
+    type Error = Error;
 
-        // match result or error
-        let data: RedditComment = match result_or_err {
-            Ok(v) => v,
-            Err(_e) => {
-                debug_comment(s);
-                panic!("Error decoding RedditComment")
-            }
-        };
-        data
+    fn try_from(s: &str) -> Result<Self> {
+        Ok(serde_json::from_str(s)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_parent_ids() {
+        let link_id = "t3_2r6t7u";
+        let link = base36::decode(link_id).unwrap();
+
+        let parent = ParentId::Int(link);
+        assert_eq!(parent.to_fullname(link_id).unwrap(), "t3_2r6t7u");
+
+        let parent = ParentId::Int(link + 1);
+        assert_eq!(parent.to_fullname(link_id).unwrap(), "t1_2r6t7v");
+        let parent = ParentId::Int(46655);
+        assert_eq!(parent.to_fullname(link_id).unwrap(), "t1_zzz");
+        let parent = ParentId::Int(0);
+        assert_eq!(parent.to_fullname(link_id).unwrap(), "t1_0");
+
+        // Without a usable link id, the parent is a comment.
+        let parent = ParentId::Int(link);
+        assert_eq!(parent.to_fullname("").unwrap(), "t1_2r6t7u");
+
+        let parent = ParentId::Int(-1);
+        assert!(matches!(parent.to_fullname(link_id), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn string_parent_ids() {
+        let parent = ParentId::String("t1_c0b2".to_string());
+        assert_eq!(parent.to_fullname("t3_abc").unwrap(), "t1_c0b2");
     }
 }
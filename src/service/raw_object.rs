@@ -1,6 +1,15 @@
+use crate::service::error::{Error, ErrorPolicy, Result};
+use serde::de::Error as _;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
-use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::chrono::{DateTime, NaiveDateTime, Utc};
+
+// Epoch values from this magnitude on are milliseconds. As seconds they
+// would be after the year 5000, as milliseconds they are after 1973.
+const MILLIS_THRESHOLD: i64 = 100_000_000_000;
+
+// ISO-8601 formats without a time zone, read as UTC.
+const NAIVE_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
 
 #[derive(Serialize, Debug)]
 pub enum AnyTimestamp {
@@ -11,7 +20,7 @@ pub enum AnyTimestamp {
 }
 
 impl<'de> Deserialize<'de> for AnyTimestamp {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
@@ -21,40 +30,233 @@ impl<'de> Deserialize<'de> for AnyTimestamp {
                 Some(int_value) => Ok(AnyTimestamp::Integer(int_value)),
                 None => match num.as_f64() {
                     Some(float_value) => Ok(AnyTimestamp::Float(float_value)),
-                    None => panic!("expected int or float, found {:?}", num),
+                    None => Err(D::Error::custom(format!(
+                        "expected int or float, found {}",
+                        num
+                    ))),
                 },
             },
             Value::String(string_value) => Ok(AnyTimestamp::String(string_value)),
             Value::Bool(bool_value) => Ok(AnyTimestamp::Bool(bool_value)),
-            _ => panic!("expected int, string, float, or bool, found {:?}", value),
+            _ => Err(D::Error::custom(format!(
+                "expected int, string, float, or bool timestamp, found {}",
+                value
+            ))),
         }
     }
 }
 
-impl From<&AnyTimestamp> for u64 {
-    fn from(created_utc: &AnyTimestamp) -> Self {
-        match created_utc {
-            AnyTimestamp::Integer(timestamp) => *timestamp as u64,
-            AnyTimestamp::String(timestamp) => timestamp.parse::<u64>().unwrap_or_default(),
-            AnyTimestamp::Float(timestamp) => *timestamp as u64,
-            AnyTimestamp::Bool(_) => 0,
+fn out_of_range(timestamp: impl std::fmt::Display) -> Error {
+    Error::Parse(format!("timestamp {} out of range", timestamp))
+}
+
+// Seconds and nanoseconds of an integer epoch, in seconds or milliseconds.
+fn epoch_int(timestamp: i64) -> (i64, u32) {
+    if timestamp.abs() >= MILLIS_THRESHOLD {
+        let nanos = timestamp.rem_euclid(1000) * 1_000_000;
+        (timestamp.div_euclid(1000), nanos as u32)
+    } else {
+        (timestamp, 0)
+    }
+}
+
+// Seconds and nanoseconds of a float epoch, in seconds or milliseconds.
+// The fractional part is kept, the database stores microseconds.
+fn epoch_float(timestamp: f64) -> Result<(i64, u32)> {
+    if !timestamp.is_finite() || timestamp.abs() >= i64::MAX as f64 {
+        return Err(out_of_range(timestamp));
+    }
+
+    // Split in the original unit, dividing first loses precision.
+    let (units_per_sec, nanos_per_unit) = if timestamp.abs() >= MILLIS_THRESHOLD as f64 {
+        (1000.0, 1e6)
+    } else {
+        (1.0, 1e9)
+    };
+    let secs = (timestamp / units_per_sec).floor();
+    let nanos = ((timestamp - secs * units_per_sec) * nanos_per_unit).round() as u32;
+    Ok((secs as i64, nanos.min(999_999_999)))
+}
+
+// Epochs written as strings, RFC 3339 dates, or ISO-8601 dates without a
+// time zone.
+fn parse_string(timestamp: &str) -> Result<(i64, u32)> {
+    let trimmed = timestamp.trim();
+
+    if let Ok(int_value) = trimmed.parse::<i64>() {
+        return Ok(epoch_int(int_value));
+    }
+    if let Ok(float_value) = trimmed.parse::<f64>() {
+        return epoch_float(float_value);
+    }
+
+    let datetime = DateTime::parse_from_rfc3339(trimmed)
+        .map(|datetime| datetime.to_utc())
+        .ok()
+        .or_else(|| {
+            NAIVE_FORMATS.iter().find_map(|format| {
+                NaiveDateTime::parse_from_str(trimmed, format)
+                    .ok()
+                    .map(|datetime| datetime.and_utc())
+            })
+        })
+        .ok_or_else(|| Error::Parse(format!("invalid timestamp {:?}", timestamp)))?;
+
+    Ok((datetime.timestamp(), datetime.timestamp_subsec_nanos()))
+}
+
+// Seconds since the epoch.
+impl TryFrom<&AnyTimestamp> for i64 {
+    type Error = Error;
+
+    fn try_from(timestamp: &AnyTimestamp) -> Result<Self> {
+        Ok(timestamp.to_parts()?.0)
+    }
+}
+
+impl TryFrom<&AnyTimestamp> for DateTime<Utc> {
+    type Error = Error;
+
+    fn try_from(timestamp: &AnyTimestamp) -> Result<Self> {
+        let (secs, nanos) = timestamp.to_parts()?;
+        DateTime::from_timestamp(secs, nanos).ok_or_else(|| out_of_range(secs))
+    }
+}
+
+impl AnyTimestamp {
+    // Seconds and nanoseconds since the epoch.
+    fn to_parts(&self) -> Result<(i64, u32)> {
+        match self {
+            AnyTimestamp::Integer(timestamp) => Ok(epoch_int(*timestamp)),
+            AnyTimestamp::String(timestamp) => parse_string(timestamp),
+            AnyTimestamp::Float(timestamp) => epoch_float(*timestamp),
+            AnyTimestamp::Bool(value) => Err(Error::Parse(format!(
+                "expected a timestamp, found {}",
+                value
+            ))),
         }
     }
+
+    /// Convert to a date. With `ErrorPolicy::Null`, invalid values are None
+    /// instead of an error.
+    pub fn to_datetime(&self, policy: ErrorPolicy) -> Result<Option<DateTime<Utc>>> {
+        match DateTime::<Utc>::try_from(self) {
+            Ok(datetime) => Ok(Some(datetime)),
+            Err(_) if policy == ErrorPolicy::Null => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Convert an optional timestamp, see `to_datetime`.
+    pub fn to_optional_datetime(
+        timestamp: Option<&AnyTimestamp>,
+        policy: ErrorPolicy,
+    ) -> Result<Option<DateTime<Utc>>> {
+        match timestamp {
+            Some(timestamp) => timestamp.to_datetime(policy),
+            None => Ok(None),
+        }
+    }
+
+    /// Same as `to_datetime`, for `edited` which is `false` for records that
+    /// were never edited, and `true` for records edited at an unknown time in
+    /// old dumps.
+    pub fn to_edited_datetime(&self, policy: ErrorPolicy) -> Result<Option<DateTime<Utc>>> {
+        match self {
+            AnyTimestamp::Bool(_) => Ok(None),
+            _ => self.to_datetime(policy),
+        }
+    }
+
+    /// Whether an `edited` value tells that the record was edited.
+    pub fn is_edited(&self) -> bool {
+        !matches!(self, AnyTimestamp::Bool(false))
+    }
 }
 
-// implement from AnyTimestamp to datetime
-impl From<&AnyTimestamp> for DateTime<Utc> {
-    fn from(created_utc: &AnyTimestamp) -> Self {
-        match created_utc {
-            AnyTimestamp::Integer(timestamp) => DateTime::from_timestamp(*timestamp, 0).unwrap(),
-            AnyTimestamp::String(timestamp) => {
-                let timestamp = timestamp.parse::<i64>().unwrap_or_default();
-                DateTime::from_timestamp(timestamp, 0).unwrap()
-            }
-            AnyTimestamp::Float(timestamp) => {
-                DateTime::from_timestamp(*timestamp as i64, 0).unwrap()
-            }
-            AnyTimestamp::Bool(_) => DateTime::from_timestamp(0, 0).unwrap(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JAN_2015: i64 = 1420070400;
+
+    #[test]
+    fn epoch_strings() {
+        assert_eq!(parse_string("1420070400").unwrap(), (JAN_2015, 0));
+        assert_eq!(parse_string("1420070400.0").unwrap(), (JAN_2015, 0));
+        assert_eq!(parse_string(" 1420070400 ").unwrap(), (JAN_2015, 0));
+    }
+
+    #[test]
+    fn float_epochs_keep_nanos() {
+        assert_eq!(epoch_float(1420070400.25).unwrap(), (JAN_2015, 250_000_000));
+        assert_eq!(
+            parse_string("1420070400.5").unwrap(),
+            (JAN_2015, 500_000_000)
+        );
+        assert_eq!(epoch_float(-0.5).unwrap(), (-1, 500_000_000));
+    }
+
+    #[test]
+    fn millisecond_epochs() {
+        assert_eq!(epoch_int(1420070400123), (JAN_2015, 123_000_000));
+        assert_eq!(epoch_int(MILLIS_THRESHOLD), (MILLIS_THRESHOLD / 1000, 0));
+        assert_eq!(epoch_int(MILLIS_THRESHOLD - 1), (MILLIS_THRESHOLD - 1, 0));
+        assert_eq!(
+            epoch_float(1420070400123.0).unwrap(),
+            (JAN_2015, 123_000_000)
+        );
+        assert_eq!(
+            parse_string("1420070400123").unwrap(),
+            (JAN_2015, 123_000_000)
+        );
+    }
+
+    #[test]
+    fn negative_millisecond_epochs() {
+        assert_eq!(epoch_int(-100_000_000_001), (-100_000_001, 999_000_000));
+        assert_eq!(epoch_int(-MILLIS_THRESHOLD), (-MILLIS_THRESHOLD / 1000, 0));
+    }
+
+    #[test]
+    fn rfc3339_dates() {
+        assert_eq!(parse_string("2015-01-01T00:00:00Z").unwrap(), (JAN_2015, 0));
+        assert_eq!(
+            parse_string("2015-01-01T02:00:00+02:00").unwrap(),
+            (JAN_2015, 0)
+        );
+        assert_eq!(
+            parse_string("2014-12-31T19:00:00.250-05:00").unwrap(),
+            (JAN_2015, 250_000_000)
+        );
+    }
+
+    #[test]
+    fn naive_dates() {
+        let datetime = DateTime::from_timestamp(JAN_2015, 250_000_000)
+            .unwrap()
+            .naive_utc();
+        for format in NAIVE_FORMATS {
+            let text = datetime.format(format).to_string();
+            assert_eq!(
+                parse_string(&text).unwrap(),
+                (JAN_2015, 250_000_000),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(parse_string("2015-01-01 00:00:00").unwrap(), (JAN_2015, 0));
+        assert_eq!(parse_string("2015-01-01T00:00:00").unwrap(), (JAN_2015, 0));
+    }
+
+    #[test]
+    fn invalid_strings() {
+        for timestamp in ["NaN", "inf", "-inf", "garbage", "", "2015-13-01T00:00:00Z"] {
+            assert!(
+                matches!(parse_string(timestamp), Err(Error::Parse(_))),
+                "{:?}",
+                timestamp
+            );
         }
     }
 }
@@ -1,8 +1,13 @@
+use super::error::{Error, Result};
 use super::raw_object::AnyTimestamp;
 use serde::{Deserialize, Serialize};
 
+/// A submission from any era of the dumps, 2005 to 2024.
+///
+/// The field set changed over the years, so only the fields present in every
+/// era are required. Fields not listed here are ignored, run
+/// `reader check-schema` to see which ones a dump carries.
 #[derive(Deserialize, Serialize, Debug)]
-#[serde(deny_unknown_fields)]
 pub struct RedditSubmission {
     pub archived: Option<bool>,
     pub author: String,
@@ -13,30 +18,34 @@ pub struct RedditSubmission {
     pub created_utc: AnyTimestamp,
     pub distinguished: Option<String>,
     pub domain: Option<String>,
-    pub edited: AnyTimestamp,
+    // `false`, or missing from some exports.
+    pub edited: Option<AnyTimestamp>,
     pub gilded: Option<i32>,
     pub hidden: Option<bool>,
     pub hide_score: Option<bool>,
     pub id: String,
     pub is_crosspostable: Option<bool>,
     pub is_reddit_media_domain: Option<bool>,
-    pub is_self: bool,
+    pub is_self: Option<bool>,
     pub is_video: Option<bool>,
     pub link_flair_css_class: Option<String>,
     pub link_flair_text: Option<String>,
     pub locked: Option<bool>,
-    pub media: serde_json::Value,
-    pub media_embed: serde_json::Value,
+    pub media: Option<serde_json::Value>,
+    pub media_embed: Option<serde_json::Value>,
     pub num_comments: i32,
     pub num_crossposts: Option<i32>,
-    pub over_18: bool,
+    pub over_18: Option<bool>,
     pub parent_whitelist_status: Option<String>,
-    pub permalink: String,
+    pub permalink: Option<String>,
     pub pinned: Option<bool>,
-    pub retrieved_on: AnyTimestamp,
+    // Renamed to `retrieved_utc` in the 2023 dumps. Some records have both.
+    pub retrieved_on: Option<AnyTimestamp>,
+    pub retrieved_utc: Option<AnyTimestamp>,
     pub score: i64,
     pub secure_media: Option<serde_json::Value>,
     pub secure_media_embed: Option<serde_json::Value>,
+    #[serde(default)]
     pub selftext: String,
     pub spoiler: Option<bool>,
     pub stickied: Option<bool>,
@@ -50,10 +59,28 @@ pub struct RedditSubmission {
     pub title: String,
     pub url: String,
     pub whitelist_status: Option<String>,
+
+    // Added from 2017 on.
+    pub crosspost_parent: Option<String>,
+    pub crosspost_parent_list: Option<Vec<serde_json::Value>>,
+    pub all_awardings: Option<Vec<serde_json::Value>>,
+    pub is_gallery: Option<bool>,
+    pub gallery_data: Option<serde_json::Value>,
+    pub media_metadata: Option<serde_json::Value>,
+    pub poll_data: Option<serde_json::Value>,
 }
 
-impl From<&str> for RedditSubmission {
-    fn from(s: &str) -> Self {
-        serde_json::from_str(s).unwrap()
+impl RedditSubmission {
+    /// When the record was retrieved, whichever name the dump uses.
+    pub fn retrieved(&self) -> Option<&AnyTimestamp> {
+        self.retrieved_on.as_ref().or(self.retrieved_utc.as_ref())
+    }
+}
+
+impl TryFrom<&str> for RedditSubmission {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self> {
+        Ok(serde_json::from_str(s)?)
     }
 }
@@ -0,0 +1,45 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+/// Output for the lines that could not be imported.
+///
+/// Each rejected line is appended as `file<TAB>line number<TAB>reason<TAB>`
+/// followed by the original bytes of the line.
+pub struct RejectedLines {
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl RejectedLines {
+    pub fn open(path: &str) -> RejectedLines {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|err| panic!("Failed to open {}: {}", path, err));
+
+        RejectedLines {
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+        }
+    }
+
+    /// Append a rejected line to the output.
+    pub fn write(&self, fname: &str, line_number: u64, reason: &str, line: &[u8]) {
+        let mut writer = self.writer.lock().unwrap();
+        let result = write!(writer, "{}\t{}\t{}\t", fname, line_number, reason)
+            .and_then(|_| writer.write_all(line))
+            .and_then(|_| writer.write_all(b"\n"));
+
+        if let Err(err) = result {
+            log::error!("Failed to write rejected line: {}", err);
+        }
+    }
+
+    /// Flush the buffered lines to disk.
+    pub fn flush(&self) {
+        if let Err(err) = self.writer.lock().unwrap().flush() {
+            log::error!("Failed to flush rejected lines: {}", err);
+        }
+    }
+}
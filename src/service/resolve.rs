@@ -0,0 +1,66 @@
+use crate::service::prefilter::may_match_subreddit;
+use crate::service::raw::{wait_zstd, zstd_command};
+use crate::service::SubredditFilter;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+#[derive(Deserialize)]
+// The only fields needed to map a subreddit name to its id.
+struct SubredditRef {
+    subreddit: Option<String>,
+    subreddit_id: Option<String>,
+}
+
+/// Scan a dump file and print the `t5_` ids used by the given subreddit
+/// names, with the names exactly as they appear in the dump and how many
+/// records carry them.
+pub async fn resolve_subreddit_ids(fname: &str, names: &[String]) {
+    let filter = SubredditFilter::new(names, &[]);
+
+    let mut child = zstd_command(fname)
+        .spawn()
+        .expect("Failed to spawn zstd process");
+    let stdout = child.stdout.take().expect("Failed to open stdout");
+    let mut reader = BufReader::new(stdout);
+
+    // Name as written in the dump -> id -> number of records.
+    let mut found: BTreeMap<String, HashMap<String, u64>> = BTreeMap::new();
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                log::error!("[{}] Read error: {}", fname, err);
+                break;
+            }
+        }
+
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_start_matches(char::from(0));
+        if !may_match_subreddit(line, &filter) {
+            continue;
+        }
+
+        let Ok(record) = serde_json::from_str::<SubredditRef>(line) else {
+            continue;
+        };
+        let (Some(name), Some(id)) = (record.subreddit, record.subreddit_id) else {
+            continue;
+        };
+
+        if filter.is_name_match(&name) {
+            *found.entry(name).or_default().entry(id).or_default() += 1;
+        }
+    }
+
+    wait_zstd(fname, child).await;
+
+    for (name, ids) in found {
+        for (id, count) in ids {
+            println!("{}\t{}\t{}", name, id, count);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use crate::service::base36::split_kind;
+use sha1::{Digest, Sha1};
+
+/// Deterministic sampling on a hash of the record ids.
+///
+/// The same ids are kept on every run and every machine, so a sample can be
+/// extended or rebuilt without changing. Ids are hashed without their type
+/// prefix, so a submission `abc` and a comment with `link_id` `t3_abc` share
+/// the same decision.
+#[derive(Clone, Copy)]
+pub struct Sampler {
+    threshold: u64,
+}
+
+impl Sampler {
+    /// Build a sampler keeping `rate` of the records. Returns None when
+    /// everything is kept.
+    pub fn new(rate: f64) -> Option<Sampler> {
+        if !(rate > 0.0 && rate <= 1.0) {
+            panic!("Invalid sample_rate {}: must be in (0, 1]", rate);
+        }
+
+        if rate == 1.0 {
+            return None;
+        }
+
+        Some(Sampler {
+            threshold: (rate * u64::MAX as f64) as u64,
+        })
+    }
+
+    /// Check if the record with the given id is part of the sample.
+    pub fn keep(&self, id: &str) -> bool {
+        let (_, id) = split_kind(id);
+        let digest = Sha1::digest(id.to_lowercase().as_bytes());
+        let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        hash < self.threshold
+    }
+}
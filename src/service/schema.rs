@@ -0,0 +1,218 @@
+use crate::service::raw::{wait_zstd, zstd_command};
+use crate::service::raw_comment::RedditComment;
+use crate::service::raw_object::AnyTimestamp;
+use crate::service::RedditSubmission;
+use chrono::Datelike;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sqlx::types::chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+
+#[derive(Deserialize)]
+// The only field needed to group the records by year.
+struct CreatedRef {
+    created_utc: Option<AnyTimestamp>,
+}
+
+#[derive(Default)]
+// Parse results of the records of one year.
+struct YearReport {
+    ok: u64,
+    failed: u64,
+    first_error: Option<String>,
+    // Fields the record structs do not know, with how many records have them.
+    ignored: BTreeMap<String, u64>,
+}
+
+// Parse a line, collecting the names of the fields that were ignored.
+fn parse<T: DeserializeOwned>(
+    line: &str,
+    ignored: &mut Vec<String>,
+) -> Result<T, serde_json::Error> {
+    let mut deserializer = serde_json::Deserializer::from_str(line);
+    serde_ignored::deserialize(&mut deserializer, |path| ignored.push(path.to_string()))
+}
+
+// Year of a record, if its creation time can be read.
+fn record_year(line: &str) -> Option<i32> {
+    let created: CreatedRef = serde_json::from_str(line).ok()?;
+    let created_utc = DateTime::<Utc>::try_from(&created.created_utc?).ok()?;
+    Some(created_utc.year())
+}
+
+/// Parse every record of a dump, compressed or not, and print per year how
+/// many records parse and which fields are ignored.
+///
+/// Files with `RS_` in their name are read as submissions, the others as
+/// comments. Returns false if any record failed to parse.
+pub async fn check_schema(fname: &str) -> bool {
+    let mut child = None;
+    let mut reader: Box<dyn AsyncBufRead + Unpin> = if fname.ends_with(".zst") {
+        let mut spawned = zstd_command(fname)
+            .spawn()
+            .expect("Failed to spawn zstd process");
+        let stdout = spawned.stdout.take().expect("Failed to open stdout");
+        child = Some(spawned);
+        Box::new(BufReader::new(stdout))
+    } else {
+        let file = tokio::fs::File::open(fname)
+            .await
+            .unwrap_or_else(|err| panic!("Failed to open {}: {}", fname, err));
+        Box::new(BufReader::new(file))
+    };
+
+    let is_submission = fname.contains("RS_");
+    let mut years: BTreeMap<Option<i32>, YearReport> = BTreeMap::new();
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                log::error!("[{}] Read error: {}", fname, err);
+                break;
+            }
+        }
+
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_start_matches(char::from(0)).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut ignored = Vec::new();
+        let result = if is_submission {
+            parse::<RedditSubmission>(line, &mut ignored).map(|_| ())
+        } else {
+            parse::<RedditComment>(line, &mut ignored).map(|_| ())
+        };
+
+        let report = years.entry(record_year(line)).or_default();
+        match result {
+            Ok(()) => report.ok += 1,
+            Err(err) => {
+                report.failed += 1;
+                report.first_error.get_or_insert(err.to_string());
+            }
+        }
+        for field in ignored {
+            *report.ignored.entry(field).or_default() += 1;
+        }
+    }
+
+    if let Some(child) = child {
+        wait_zstd(fname, child).await;
+    }
+
+    let mut all_ok = true;
+    for (year, report) in &years {
+        let year = year.map_or_else(|| "unknown".to_string(), |year| year.to_string());
+        println!("{}: {} ok, {} failed", year, report.ok, report.failed);
+
+        if let Some(error) = &report.first_error {
+            println!("    first error: {}", error);
+            all_ok = false;
+        }
+        if !report.ignored.is_empty() {
+            let fields: Vec<String> = report
+                .ignored
+                .iter()
+                .map(|(field, count)| format!("{} ({})", field, count))
+                .collect();
+            println!("    ignored: {}", fields.join(", "));
+        }
+    }
+
+    all_ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{DBRedditSubmission, ErrorPolicy};
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    // Submissions from every era of the dumps.
+    const CORPUS: &str = include_str!("../../corpus/RS_2005-2024.jsonl");
+
+    #[test]
+    fn corpus_submissions_parse() {
+        let mut years = vec![];
+        let mut absent: HashMap<&str, usize> = HashMap::new();
+        for (number, line) in CORPUS.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let submission = RedditSubmission::try_from(line)
+                .unwrap_or_else(|err| panic!("line {}: {}", number + 1, err));
+            let db_submission = DBRedditSubmission::new(&submission, ErrorPolicy::Reject)
+                .unwrap_or_else(|err| panic!("line {}: {}", number + 1, err));
+
+            let created_utc = db_submission.created_utc.unwrap();
+            let year = created_utc.year();
+            years.push(year);
+            assert!(
+                submission.retrieved().is_some(),
+                "{}: no retrieval time",
+                year
+            );
+            let retrieved_on = db_submission.retrieved_on.unwrap();
+            assert!(
+                retrieved_on > created_utc,
+                "{}: retrieved before created",
+                year
+            );
+
+            let expected = match (&submission.retrieved_on, &submission.retrieved_utc) {
+                // Dumps with both names: `retrieved_on` is kept.
+                (Some(retrieved_on), _) => retrieved_on,
+                (None, Some(retrieved_utc)) => retrieved_utc,
+                (None, None) => unreachable!(),
+            };
+            assert_eq!(
+                Some(retrieved_on),
+                expected.to_datetime(ErrorPolicy::Reject).unwrap(),
+                "{}",
+                year
+            );
+
+            let fields: Value = serde_json::from_str(line).unwrap();
+            for field in ["media", "is_self", "retrieved_on", "retrieved_utc"] {
+                if fields.get(field).is_none() {
+                    *absent.entry(field).or_default() += 1;
+                }
+            }
+            if fields.get("media").is_none() {
+                assert_eq!(db_submission.media, None);
+            }
+            if fields.get("is_self").is_none() {
+                assert!(!db_submission.is_self);
+            }
+        }
+
+        assert_eq!(years, (2005..=2024).collect::<Vec<_>>());
+        for field in ["media", "is_self", "retrieved_on", "retrieved_utc"] {
+            assert!(absent.contains_key(field), "no record without {}", field);
+        }
+
+        // 2023 only has `retrieved_utc`, 2024 has both.
+        let lines: Vec<&str> = CORPUS.lines().collect();
+        let submission = RedditSubmission::try_from(lines[18]).unwrap();
+        assert!(submission.retrieved_on.is_none() && submission.retrieved_utc.is_some());
+        let submission = RedditSubmission::try_from(lines[19]).unwrap();
+        assert!(submission.retrieved_on.is_some() && submission.retrieved_utc.is_some());
+        let db_submission = DBRedditSubmission::new(&submission, ErrorPolicy::Reject).unwrap();
+        assert_ne!(
+            db_submission.retrieved_on,
+            submission
+                .retrieved_utc
+                .as_ref()
+                .unwrap()
+                .to_datetime(ErrorPolicy::Reject)
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use crate::service::cfg::{ThresholdConfig, ThresholdRules};
+use crate::service::db_object::{DELETED, REMOVED};
+use crate::service::expr::{Field, FieldValue, FilterRecord};
+use crate::service::sample::Sampler;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Authors and texts of deleted or removed records.
+const DELETED_VALUES: [&str; 2] = [DELETED, REMOVED];
+
+fn is_deleted(record: &dyn FilterRecord, field: Field) -> bool {
+    matches!(record.field(field), FieldValue::Str(value) if DELETED_VALUES.contains(&value))
+}
+
+fn is_below(record: &dyn FilterRecord, field: Field, min: Option<i64>) -> bool {
+    match (record.field(field), min) {
+        (FieldValue::Int(value), Some(min)) => value < min,
+        _ => false,
+    }
+}
+
+/// Threshold rules of one kind of record, with the number of records each
+/// rule dropped.
+pub struct KindThresholds {
+    rules: ThresholdRules,
+    // Sampling decision, and the id field it is made on.
+    sampler: Option<Sampler>,
+    sample_field: Field,
+    sampled_out: AtomicU64,
+    deleted_author: AtomicU64,
+    deleted_body: AtomicU64,
+    min_score: AtomicU64,
+    min_num_comments: AtomicU64,
+}
+
+impl KindThresholds {
+    fn new(rules: ThresholdRules, sampler: Option<Sampler>, sample_field: Field) -> KindThresholds {
+        KindThresholds {
+            rules,
+            sampler,
+            sample_field,
+            sampled_out: AtomicU64::new(0),
+            deleted_author: AtomicU64::new(0),
+            deleted_body: AtomicU64::new(0),
+            min_score: AtomicU64::new(0),
+            min_num_comments: AtomicU64::new(0),
+        }
+    }
+
+    /// Check if a selected record passes the thresholds.
+    ///
+    /// A dropped record is counted once, against the first rule it fails.
+    pub fn keep(&self, record: &dyn FilterRecord) -> bool {
+        let dropped_by = if self.is_sampled_out(record) {
+            &self.sampled_out
+        } else if self.rules.skip_deleted_authors && is_deleted(record, Field::Author) {
+            &self.deleted_author
+        } else if self.rules.skip_deleted_bodies
+            && (is_deleted(record, Field::Body) || is_deleted(record, Field::Selftext))
+        {
+            &self.deleted_body
+        } else if is_below(record, Field::Score, self.rules.min_score) {
+            &self.min_score
+        } else if is_below(record, Field::NumComments, self.rules.min_num_comments) {
+            &self.min_num_comments
+        } else {
+            return true;
+        };
+
+        dropped_by.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    fn is_sampled_out(&self, record: &dyn FilterRecord) -> bool {
+        let Some(sampler) = &self.sampler else {
+            return false;
+        };
+
+        match record.field(self.sample_field) {
+            FieldValue::Str(id) => !sampler.keep(id),
+            _ => false,
+        }
+    }
+
+    fn report(&self, kind: &str) {
+        let counts = [
+            ("sample_rate", &self.sampled_out),
+            ("skip_deleted_authors", &self.deleted_author),
+            ("skip_deleted_bodies", &self.deleted_body),
+            ("min_score", &self.min_score),
+            ("min_num_comments", &self.min_num_comments),
+        ];
+
+        for (rule, count) in counts {
+            let count = count.load(Ordering::Relaxed);
+            if count > 0 {
+                log::info!("{}: {} dropped by {}", kind, count, rule);
+                println!("{:<12} {:>10} dropped by {}", kind, count, rule);
+            }
+        }
+    }
+}
+
+/// Thresholds applied to the selected records before they are inserted.
+pub struct Thresholds {
+    pub submissions: KindThresholds,
+    pub comments: KindThresholds,
+}
+
+impl Thresholds {
+    /// Build the thresholds, sampling `sample_rate` of the records.
+    ///
+    /// With `sample_by_thread`, comments are sampled on their `link_id` so
+    /// they follow the decision made for their submission.
+    pub fn new(config: &ThresholdConfig, sample_rate: f64, sample_by_thread: bool) -> Thresholds {
+        let sampler = Sampler::new(sample_rate);
+        let comment_field = if sample_by_thread {
+            Field::LinkId
+        } else {
+            Field::Id
+        };
+
+        Thresholds {
+            submissions: KindThresholds::new(config.submissions.clone(), sampler, Field::Id),
+            comments: KindThresholds::new(config.comments.clone(), sampler, comment_field),
+        }
+    }
+
+    /// Print the number of records dropped by each rule.
+    pub fn report(&self) {
+        self.submissions.report("submissions");
+        self.comments.report("comments");
+    }
+}
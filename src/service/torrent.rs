@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+const VERIFIED_FILE: &str = "verified.json";
+const SHA1_LEN: usize = 20;
+
+#[derive(Deserialize)]
+struct Torrent {
+    info: TorrentInfo,
+}
+
+#[derive(Deserialize)]
+struct TorrentInfo {
+    name: String,
+    #[serde(rename = "piece length")]
+    piece_length: u64,
+    pieces: ByteBuf,
+    length: Option<u64>,
+    files: Option<Vec<TorrentFile>>,
+}
+
+#[derive(Deserialize)]
+struct TorrentFile {
+    length: u64,
+    path: Vec<String>,
+}
+
+/// What to do with a file that failed verification.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyPolicy {
+    Ignore,
+    #[default]
+    Warn,
+    Refuse,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyStatus {
+    Verified,
+    Failed,
+    // None of the pieces could be checked, they all span missing files.
+    Unverified,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+/// Verification result of a single dump file.
+pub struct VerifiedFile {
+    pub status: VerifyStatus,
+    pub size: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Default)]
+/// Verification results of all the dump files, saved in `verified.json`.
+pub struct VerifyRecord {
+    files: HashMap<String, VerifiedFile>,
+}
+
+impl VerifyRecord {
+    pub fn load() -> VerifyRecord {
+        if std::fs::metadata(VERIFIED_FILE).is_ok() {
+            let contents = std::fs::read_to_string(VERIFIED_FILE).unwrap();
+            serde_json::from_str(&contents).unwrap()
+        } else {
+            VerifyRecord::default()
+        }
+    }
+
+    fn save_to_file(&self) {
+        let contents = serde_json::to_string_pretty(&self).unwrap();
+        std::fs::write(VERIFIED_FILE, contents).unwrap();
+    }
+
+    /// Get the verification result of a file, if it is still valid.
+    ///
+    /// Results recorded for a different file size are stale and ignored.
+    pub fn get(&self, fname: &str) -> Option<&VerifiedFile> {
+        let file = self.files.get(fname)?;
+        let size = std::fs::metadata(fname).ok()?.len();
+
+        if file.size != size {
+            return None;
+        }
+
+        Some(file)
+    }
+}
+
+// A file from the torrent, placed in the concatenated torrent data.
+struct Entry {
+    local_path: Option<PathBuf>,
+    // Only files of the expected size are read.
+    readable: bool,
+    offset: u64,
+    length: u64,
+    // Number of pieces checked and left unchecked.
+    checked: u64,
+    skipped: u64,
+    error: Option<String>,
+}
+
+// Find the local copy of a torrent file, with or without the torrent name
+// as the top folder.
+fn find_local(target_folder: &Path, name: &str, path: &[String]) -> Option<PathBuf> {
+    let relative: PathBuf = path.iter().collect();
+    [
+        target_folder.join(&relative),
+        target_folder.join(name).join(&relative),
+    ]
+    .into_iter()
+    .find(|candidate| candidate.is_file())
+}
+
+// Read `[start, end)` of the concatenated torrent data.
+// Returns None when one of the files holding the range is missing locally.
+fn read_range(entries: &[Entry], start: u64, end: u64) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity((end - start) as usize);
+
+    for entry in entries {
+        let entry_end = entry.offset + entry.length;
+        if entry_end <= start || entry.offset >= end {
+            continue;
+        }
+
+        if !entry.readable {
+            return None;
+        }
+        let local_path = entry.local_path.as_ref()?;
+        let from = start.max(entry.offset) - entry.offset;
+        let to = end.min(entry_end) - entry.offset;
+
+        let mut file = File::open(local_path).ok()?;
+        file.seek(SeekFrom::Start(from)).ok()?;
+
+        let mut chunk = vec![0u8; (to - from) as usize];
+        file.read_exact(&mut chunk).ok()?;
+        data.extend_from_slice(&chunk);
+    }
+
+    Some(data)
+}
+
+/// Check the dump files under `target_folder` against the piece hashes of a
+/// `.torrent` file and record the results in `verified.json`.
+pub fn verify_dumps(torrent_path: &str, target_folder: &str) {
+    let contents = std::fs::read(torrent_path)
+        .unwrap_or_else(|err| panic!("Failed to read {}: {}", torrent_path, err));
+    let torrent: Torrent = serde_bencode::from_bytes(&contents)
+        .unwrap_or_else(|err| panic!("Failed to parse {}: {}", torrent_path, err));
+    let info = torrent.info;
+
+    if !info.pieces.len().is_multiple_of(SHA1_LEN) {
+        panic!("Invalid piece hashes in {}", torrent_path);
+    }
+
+    // Single file torrents have no file list, only a name and a length.
+    let files = match (info.files, info.length) {
+        (Some(files), _) => files,
+        (None, Some(length)) => vec![TorrentFile {
+            length,
+            path: vec![info.name.clone()],
+        }],
+        (None, None) => panic!("Torrent {} lists no files", torrent_path),
+    };
+
+    let root = Path::new(target_folder);
+    let mut offset = 0;
+    let mut entries: Vec<Entry> = Vec::new();
+    for file in files {
+        let local_path = find_local(root, &info.name, &file.path);
+        let mut error = None;
+
+        // A file with the wrong size cannot match any of its pieces.
+        if let Some(path) = &local_path {
+            let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+            if size != file.length {
+                error = Some(format!("expected {} bytes, found {}", file.length, size));
+            }
+        }
+
+        entries.push(Entry {
+            readable: local_path.is_some() && error.is_none(),
+            local_path,
+            offset,
+            length: file.length,
+            checked: 0,
+            skipped: 0,
+            error,
+        });
+        offset += file.length;
+    }
+    let total_length = offset;
+
+    let hashes: Vec<&[u8]> = info.pieces.chunks(SHA1_LEN).collect();
+    for (index, expected) in hashes.iter().enumerate() {
+        let start = index as u64 * info.piece_length;
+        let end = (start + info.piece_length).min(total_length);
+
+        let overlapping: Vec<usize> = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.offset < end && e.offset + e.length > start)
+            .map(|(i, _)| i)
+            .collect();
+
+        // Nothing to check if none of the files is available locally.
+        if overlapping.iter().all(|&i| !entries[i].readable) {
+            continue;
+        }
+
+        let Some(data) = read_range(&entries, start, end) else {
+            // Pieces shared with a file we do not have cannot be checked.
+            for &i in &overlapping {
+                entries[i].skipped += 1;
+            }
+            continue;
+        };
+
+        let matches = Sha1::digest(&data).as_slice() == *expected;
+        for &i in &overlapping {
+            entries[i].checked += 1;
+            if !matches && entries[i].error.is_none() {
+                entries[i].error = Some(format!("piece {} hash mismatch", index));
+            }
+        }
+
+        if index.is_multiple_of(1000) {
+            log::info!("Verified {}/{} pieces", index, hashes.len());
+        }
+    }
+
+    let mut record = VerifyRecord::load();
+    for entry in entries {
+        let Some(local_path) = entry.local_path else {
+            continue;
+        };
+
+        let fname = local_path.to_str().unwrap().to_string();
+        let size = std::fs::metadata(&local_path).map(|m| m.len()).unwrap_or(0);
+        let status = match &entry.error {
+            Some(error) => {
+                log::error!("[{}] Verification failed: {}", fname, error);
+                println!("FAILED   {} ({})", fname, error);
+                VerifyStatus::Failed
+            }
+            None if entry.checked == 0 => {
+                log::warn!(
+                    "[{}] No piece could be checked, {} span missing files",
+                    fname,
+                    entry.skipped
+                );
+                println!("UNCHECKED {} (no complete piece)", fname);
+                VerifyStatus::Unverified
+            }
+            None => {
+                if entry.skipped > 0 {
+                    log::info!(
+                        "[{}] {} boundary pieces could not be checked",
+                        fname,
+                        entry.skipped
+                    );
+                }
+                println!("OK       {} ({} pieces)", fname, entry.checked);
+                VerifyStatus::Verified
+            }
+        };
+
+        record.files.insert(
+            fname,
+            VerifiedFile {
+                status,
+                size,
+                error: entry.error,
+            },
+        );
+    }
+
+    record.save_to_file();
+}
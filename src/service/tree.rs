@@ -0,0 +1,208 @@
+use crate::service::{DBRedditSubmission, DBTable};
+use sqlx::PgPool;
+
+// Columns read and written to build the trees.
+const TREE_COLUMNS: [&str; 9] = [
+    "id",
+    "id_num",
+    "link_id_num",
+    "parent_kind",
+    "parent_id_num",
+    "depth",
+    "root_comment_id",
+    "path",
+    "orphan",
+];
+
+// Run an update of the tree columns, returning the number of rows updated.
+async fn update(pool: &PgPool, sql: &str) -> u64 {
+    sqlx::query(sql)
+        .execute(pool)
+        .await
+        .unwrap_or_else(|err| panic!("Failed to build the comment trees: {}", err))
+        .rows_affected()
+}
+
+/// Fill `depth`, `root_comment_id`, `path` and `orphan` for the comments
+/// that do not have them yet.
+///
+/// `path` holds the numeric ids from the submission down to the comment.
+/// Comments whose parent comment is missing are flagged as orphans, together
+/// with their replies. Their `path` starts with the missing parent, and
+/// their `depth` and `root_comment_id` are unknown. Orphans are built again
+/// on every run, in case their parent was imported since.
+///
+/// Comments whose submission is missing from `submissions` are flagged with
+/// `missing_submission`, checked again on every run.
+pub async fn build_comment_trees<T: DBTable>(pool: &PgPool) {
+    let table = T::table_name();
+    for column in TREE_COLUMNS {
+        if !T::has_column(column) {
+            panic!(
+                "Column {} of {} is needed to build the trees",
+                column, table
+            );
+        }
+    }
+
+    for (column, method) in [("root_comment_id", "btree"), ("path", "gin")] {
+        sqlx::query(&format!(
+            "CREATE INDEX IF NOT EXISTS {0}_{1}_idx ON {0} USING {2} ({1})",
+            table, column, method
+        ))
+        .execute(pool)
+        .await
+        .expect("Failed to create index.");
+    }
+
+    // Every step looks for the comments without a tree, which are few once
+    // the first trees are built.
+    sqlx::query(&format!(
+        "CREATE INDEX IF NOT EXISTS {0}_unbuilt_idx ON {0} (parent_id_num) WHERE path IS NULL",
+        table
+    ))
+    .execute(pool)
+    .await
+    .expect("Failed to create index.");
+
+    let reset = update(
+        pool,
+        &format!(
+            "UPDATE {} SET depth = NULL, root_comment_id = NULL, path = NULL, orphan = NULL \
+            WHERE orphan",
+            table
+        ),
+    )
+    .await;
+    log::info!("[{}] Rebuilding {} orphan comments", table, reset);
+
+    // Top level comments, then one level of replies at a time.
+    let mut built = update(
+        pool,
+        &format!(
+            "UPDATE {} SET depth = 0, root_comment_id = id, path = ARRAY[link_id_num, id_num], \
+            orphan = false WHERE path IS NULL AND parent_kind = 't3'",
+            table
+        ),
+    )
+    .await;
+    log::info!("[{}] {} top level comments", table, built);
+
+    let replies = format!(
+        "UPDATE {0} AS c SET depth = p.depth + 1, root_comment_id = p.root_comment_id, \
+        path = p.path || c.id_num, orphan = p.orphan FROM {0} AS p \
+        WHERE c.path IS NULL AND c.parent_kind = 't1' AND p.id_num = c.parent_id_num \
+        AND p.path IS NOT NULL",
+        table
+    );
+    loop {
+        let mut step = 1;
+        loop {
+            let count = update(pool, &replies).await;
+            if count == 0 {
+                break;
+            }
+            log::info!("[{}] {} replies built in step {}", table, count, step);
+            built += count;
+            step += 1;
+        }
+
+        // Replies whose parent is missing start a detached tree, their
+        // replies are built by the next pass.
+        let count = update(
+            pool,
+            &format!(
+                "UPDATE {0} AS c SET path = ARRAY[link_id_num, parent_id_num, id_num], \
+                orphan = true WHERE path IS NULL AND parent_kind = 't1' \
+                AND parent_id_num IS NOT NULL AND NOT EXISTS (SELECT 1 FROM {0} AS p WHERE p.id_num = c.parent_id_num)",
+                table
+            ),
+        )
+        .await;
+        if count == 0 {
+            break;
+        }
+        log::info!("[{}] {} comments with a missing parent", table, count);
+        built += count;
+    }
+
+    // Comments without a usable parent id, or in a cycle.
+    let unknown = update(
+        pool,
+        &format!(
+            "UPDATE {} SET path = ARRAY[link_id_num, id_num], orphan = true WHERE path IS NULL",
+            table
+        ),
+    )
+    .await;
+    if unknown > 0 {
+        log::warn!("[{}] {} comments without a usable parent", table, unknown);
+    }
+
+    let total_orphans: i64 =
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {} WHERE orphan", table))
+            .fetch_one(pool)
+            .await
+            .expect("Failed to count orphans.");
+
+    log::info!(
+        "[{}] Built {} comments, {} orphans",
+        table,
+        built + unknown,
+        total_orphans
+    );
+    println!(
+        "{}: built the tree of {} comments, {} orphans",
+        table,
+        built + unknown,
+        total_orphans
+    );
+
+    flag_missing_submissions::<T>(pool).await;
+}
+
+// Flag the comments whose submission is not imported. Comments flagged by an
+// earlier run are checked again, in case their submission was imported since.
+async fn flag_missing_submissions<T: DBTable>(pool: &PgPool) {
+    let table = T::table_name();
+    let submissions = DBRedditSubmission::table_name();
+    if !T::has_column("missing_submission") || !DBRedditSubmission::has_column("id_num") {
+        log::warn!(
+            "[{}] Missing submissions need missing_submission and {}.id_num",
+            table,
+            submissions
+        );
+        return;
+    }
+
+    sqlx::query(&format!(
+        "CREATE INDEX IF NOT EXISTS {0}_missing_submission_idx ON {0} (link_id_num) \
+        WHERE missing_submission IS NOT FALSE",
+        table
+    ))
+    .execute(pool)
+    .await
+    .expect("Failed to create index.");
+
+    update(
+        pool,
+        &format!(
+            "UPDATE {0} AS c SET missing_submission = NOT EXISTS \
+            (SELECT 1 FROM {1} AS s WHERE s.id_num = c.link_id_num) \
+            WHERE c.missing_submission IS NOT FALSE",
+            table, submissions
+        ),
+    )
+    .await;
+
+    let missing: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM {} WHERE missing_submission",
+        table
+    ))
+    .fetch_one(pool)
+    .await
+    .expect("Failed to count missing submissions.");
+
+    log::info!("[{}] {} comments without their submission", table, missing);
+    println!("{}: {} comments without their submission", table, missing);
+}